@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use failure::err_msg;
+use failure::Error;
+use hmac::Hmac;
+use hmac::Mac;
+use sha2::Sha256;
+use tantivy::Document;
+use tantivy::Index;
+use tantivy::merge_policy::NoMergePolicy;
+use tantivy::schema::Facet;
+use tantivy::schema::Field;
+use tantivy::schema::FieldType;
+use tantivy::schema::FieldValue;
+use tantivy::schema::Value;
+
+use dump::for_each_document;
+use dump::WRITER_HEAP_BYTES;
+use reconstruct::tokenize;
+use top_terms::top_terms;
+use TantivyViewerError;
+
+/// Rewrites text, bytes and facet values with hashes keyed by a secret, so the same term
+/// always maps to the same hash for a given key and query terms can be rewritten to match.
+///
+/// Hashes are HMAC-SHA256 truncated to their first 64 bits, so they line up across builds
+/// and versions of `tantivy-viewer`.
+pub struct Anonymizer {
+    mac: Hmac<Sha256>,
+    /// Per text field, the terms left in clear text.
+    kept: HashMap<Field, HashSet<String>>,
+}
+
+impl Anonymizer {
+    /// Keeps the `keep_top` most common terms of every indexed text field in clear text.
+    pub fn new(index: &Index, key: &str, keep_top: usize) -> Result<Anonymizer, Error> {
+        let schema = index.schema();
+        let mut kept = HashMap::new();
+        if keep_top > 0 {
+            for (idx, field_entry) in schema.fields().iter().enumerate() {
+                if let FieldType::Str(ref options) = *field_entry.field_type() {
+                    if options.get_indexing_options().is_some() {
+                        let terms = top_terms(index, field_entry.name(), keep_top)?.terms
+                            .into_iter()
+                            .map(|x| format!("{}", x.term))
+                            .collect();
+                        kept.insert(Field(idx as u32), terms);
+                    }
+                }
+            }
+        }
+        if key.is_empty() {
+            return Err(err_msg("The anonymization key is empty"));
+        }
+        let mac = Hmac::<Sha256>::new_varkey(key.as_bytes()).map_err(|_e| err_msg("Invalid anonymization key"))?;
+        Ok(Anonymizer { mac, kept })
+    }
+
+    fn hash(&self, bytes: &[u8]) -> u64 {
+        let mut mac = self.mac.clone();
+        mac.input(bytes);
+        mac.result().code()[..8].iter().fold(0u64, |hash, &byte| (hash << 8) | byte as u64)
+    }
+
+    fn anonymize_term(&self, field: Field, term: &str) -> String {
+        let kept = self.kept.get(&field).map(|x| x.contains(term)).unwrap_or(false);
+        if kept {
+            term.to_string()
+        } else {
+            format!("{:016x}", self.hash(term.as_bytes()))
+        }
+    }
+
+    /// Tokenized text is rewritten token by token, and the tokens joined with spaces. Only the
+    /// tokens the tokenizer keeps survive, so dropped stop words close up their gaps and phrase
+    /// queries across them can match differently. Text that isn't tokenized is rewritten whole.
+    pub fn anonymize_text(&self, index: &Index, field: Field, text: &str) -> Result<String, Error> {
+        let schema = index.schema();
+        let options = match *schema.get_field_entry(field).field_type() {
+            FieldType::Str(ref options) => options.clone(),
+            _ => return Err(err_msg("Not a text field")),
+        };
+        let tokenized = options.get_indexing_options().map(|x| x.tokenizer() != "raw").unwrap_or(false);
+        if !tokenized {
+            return Ok(self.anonymize_term(field, text));
+        }
+        Ok(tokenize(index, &options, text)?
+            .iter()
+            .map(|token| self.anonymize_term(field, &token.text))
+            .collect::<Vec<_>>()
+            .join(" "))
+    }
+
+    fn anonymize_facet(&self, facet: &Facet) -> Facet {
+        let path = format!("{}", facet)
+            .split('/')
+            .filter(|x| !x.is_empty())
+            .map(|x| format!("/{:016x}", self.hash(x.as_bytes())))
+            .collect::<String>();
+        // `Facet::from_text` expects the leading slash, which the root facet's path lacks here.
+        if path.is_empty() {
+            Facet::root()
+        } else {
+            Facet::from_text(&path)
+        }
+    }
+
+    /// Numeric values are kept as they are, so their distributions carry over.
+    pub fn anonymize_document(&self, index: &Index, document: &Document) -> Result<Document, Error> {
+        let mut anonymized = Document::default();
+        for field_value in document.field_values() {
+            let field = field_value.field();
+            let value = match *field_value.value() {
+                Value::Str(ref text) => Value::Str(self.anonymize_text(index, field, text)?),
+                Value::Bytes(ref bytes) => Value::Bytes(format!("{:016x}", self.hash(bytes)).into_bytes()),
+                Value::Facet(ref facet) => Value::Facet(self.anonymize_facet(facet)),
+                ref value => value.clone(),
+            };
+            anonymized.add(FieldValue::new(field, value));
+        }
+        Ok(anonymized)
+    }
+}
+
+pub struct AnonymizeStats {
+    pub num_docs: u64,
+    pub num_segments: usize,
+}
+
+/// Writes an anonymized copy of every live doc into a new index at `output_dir`, with the
+/// same schema.
+///
+/// Each source segment is committed on its own with merging turned off, so the new index
+/// normally ends up with the same segments holding the same docs. A segment too large for
+/// the writer's heap is still split.
+pub fn anonymize(index: &Index, anonymizer: &Anonymizer, output_dir: &Path) -> Result<AnonymizeStats, Error> {
+    if output_dir.exists() && fs::read_dir(output_dir)?.next().is_some() {
+        return Err(err_msg(format!("{} is not empty", output_dir.display())));
+    }
+    fs::create_dir_all(output_dir)?;
+    let anonymized = Index::create_in_dir(output_dir, index.schema()).map_err(TantivyViewerError::TantivyError)?;
+    let mut writer = anonymized.writer_with_num_threads(1, WRITER_HEAP_BYTES).map_err(TantivyViewerError::TantivyError)?;
+    writer.set_merge_policy(Box::new(NoMergePolicy));
+
+    let mut stats = AnonymizeStats { num_docs: 0, num_segments: 0 };
+    let mut current_segment = None;
+    for_each_document(index, None, |segment, _doc, document| {
+        if current_segment != Some(segment.segment_id()) {
+            if current_segment.is_some() {
+                writer.commit().map_err(TantivyViewerError::TantivyError)?;
+            }
+            current_segment = Some(segment.segment_id());
+            stats.num_segments += 1;
+        }
+        writer.add_document(anonymizer.anonymize_document(index, &document)?);
+        stats.num_docs += 1;
+        Ok(())
+    })?;
+    writer.commit().map_err(TantivyViewerError::TantivyError)?;
+    writer.wait_merging_threads().map_err(TantivyViewerError::TantivyError)?;
+    Ok(stats)
+}
+
+pub fn run_anonymize(index: &Index, key: &str, keep_top: usize, output_dir: &Path) -> Result<(), Error> {
+    let anonymizer = Anonymizer::new(index, key, keep_top)?;
+    let stats = anonymize(index, &anonymizer, output_dir)?;
+    println!("Anonymized {} docs in {} segments into {}", stats.num_docs, stats.num_segments, output_dir.display());
+    Ok(())
+}
+
+/// Prints `text` as it would be written into an index anonymized with the same key and
+/// `keep_top`, for rewriting the terms of a query.
+pub fn run_anonymize_text(index: &Index, key: &str, keep_top: usize, field_name: &str, text: &str) -> Result<(), Error> {
+    let field = index.schema().get_field(field_name).ok_or(err_msg("Sorry, that field does not exist!"))?;
+    let anonymizer = Anonymizer::new(index, key, keep_top)?;
+    println!("{}", anonymizer.anonymize_text(index, field, text)?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tantivy::schema::SchemaBuilder;
+    use tantivy::schema::STRING;
+    use tantivy::schema::TEXT;
+
+    fn test_index() -> Index {
+        let mut schema_builder = SchemaBuilder::default();
+        let body = schema_builder.add_text_field("body", TEXT);
+        schema_builder.add_text_field("id", STRING);
+        let index = Index::create_in_ram(schema_builder.build());
+        let mut writer = index.writer_with_num_threads(1, 40_000_000).unwrap();
+        for text in &["common words", "common things", "rare"] {
+            let mut document = Document::default();
+            document.add_text(body, text);
+            writer.add_document(document);
+        }
+        writer.commit().unwrap();
+        index.load_searchers().unwrap();
+        index
+    }
+
+    #[test]
+    fn test_hash_is_truncated_hmac_sha256() {
+        // RFC 4231, test case 2.
+        let anonymizer = Anonymizer::new(&test_index(), "Jefe", 0).unwrap();
+        assert_eq!(anonymizer.hash(b"what do ya want for nothing?"), 0x5bdcc146bf60754e);
+        assert_eq!(anonymizer.anonymize_term(Field(0), "what do ya want for nothing?"), "5bdcc146bf60754e");
+    }
+
+    #[test]
+    fn test_hash_depends_on_the_key() {
+        let index = test_index();
+        let a = Anonymizer::new(&index, "key a", 0).unwrap();
+        let b = Anonymizer::new(&index, "key b", 0).unwrap();
+        assert_eq!(a.anonymize_term(Field(0), "term"), Anonymizer::new(&index, "key a", 0).unwrap().anonymize_term(Field(0), "term"));
+        assert!(a.anonymize_term(Field(0), "term") != b.anonymize_term(Field(0), "term"));
+        assert!(Anonymizer::new(&index, "", 0).is_err());
+    }
+
+    #[test]
+    fn test_anonymize_text_keeps_top_terms() {
+        let index = test_index();
+        let anonymizer = Anonymizer::new(&index, "secret", 1).unwrap();
+        let body = index.schema().get_field("body").unwrap();
+        let id = index.schema().get_field("id").unwrap();
+        let rare = anonymizer.anonymize_term(body, "rare");
+        assert_eq!(anonymizer.anonymize_text(&index, body, "Common, rare!").unwrap(), format!("common {}", rare));
+        // Untokenized text is hashed whole, and only text fields keep their top terms.
+        assert_eq!(anonymizer.anonymize_text(&index, id, "common").unwrap(), anonymizer.anonymize_term(id, "common"));
+        assert!(anonymizer.anonymize_text(&index, id, "common").unwrap() != "common");
+    }
+
+    #[test]
+    fn test_anonymize_facet_keeps_the_hierarchy() {
+        let anonymizer = Anonymizer::new(&test_index(), "secret", 0).unwrap();
+        let parent = format!("{}", anonymizer.anonymize_facet(&Facet::from_text("/music/rock")));
+        let child = format!("{}", anonymizer.anonymize_facet(&Facet::from_text("/music/rock/punk")));
+        assert_eq!(parent, format!("/{:016x}/{:016x}", anonymizer.hash(b"music"), anonymizer.hash(b"rock")));
+        assert!(child.starts_with(&format!("{}/", parent)));
+        assert_eq!(format!("{}", anonymizer.anonymize_facet(&Facet::root())), "/");
+    }
+}
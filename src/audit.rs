@@ -0,0 +1,284 @@
+use std::collections::HashMap;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use actix_web::Query;
+use failure::Error;
+use tantivy::DocId;
+use tantivy::Document;
+use tantivy::Index;
+use tantivy::SegmentReader;
+use tantivy::schema::Field;
+use tantivy::schema::FieldEntry;
+use tantivy::schema::FieldType;
+use tantivy::schema::Value;
+
+use output::print_json;
+use output::print_table;
+use output::OutputFormat;
+use reconstruct::reconstruct;
+use reconstruct::reconstruct_numeric;
+use reconstruct::tokenize;
+use top_terms::TantivyValue;
+use State;
+use TantivyViewerError;
+
+const MAX_EXAMPLES: usize = 10;
+
+#[derive(Serialize)]
+pub struct Mismatch {
+    segment: String,
+    doc: DocId,
+    stored: String,
+    rebuilt: String,
+}
+
+#[derive(Serialize)]
+pub struct FieldAudit {
+    field: String,
+    source: String,
+    docs_checked: usize,
+    mismatches: usize,
+    mismatch_rate: String,
+    examples: Vec<Mismatch>,
+}
+
+impl FieldAudit {
+    fn new(field: &str, source: &str) -> FieldAudit {
+        FieldAudit {
+            field: field.to_string(),
+            source: source.to_string(),
+            docs_checked: 0,
+            mismatches: 0,
+            mismatch_rate: String::new(),
+            examples: Vec::new(),
+        }
+    }
+
+    fn record(&mut self, segment: &SegmentReader, doc: DocId, mut stored: Vec<TantivyValue>, mut rebuilt: Vec<TantivyValue>) {
+        self.docs_checked += 1;
+        stored.sort();
+        rebuilt.sort();
+        if stored != rebuilt {
+            self.mismatches += 1;
+            if self.examples.len() < MAX_EXAMPLES {
+                self.examples.push(Mismatch {
+                    segment: segment.segment_id().uuid_string(),
+                    doc,
+                    stored: join_values(&stored),
+                    rebuilt: join_values(&rebuilt),
+                });
+            }
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct AuditData {
+    sample: usize,
+    fields: Vec<FieldAudit>,
+}
+
+fn join_values(values: &[TantivyValue]) -> String {
+    values.iter().map(|x| format!("{}", x)).collect::<Vec<_>>().join(" ")
+}
+
+fn is_indexed(field_type: &FieldType) -> bool {
+    field_type.get_index_record_option().is_some()
+}
+
+fn is_fast(field_type: &FieldType) -> bool {
+    match *field_type {
+        FieldType::U64(ref opts) | FieldType::I64(ref opts) => opts.is_fast(),
+        _ => false,
+    }
+}
+
+fn is_auditable(field_entry: &FieldEntry) -> bool {
+    let field_type = field_entry.field_type();
+    match *field_type {
+        FieldType::HierarchicalFacet | FieldType::Bytes => false,
+        _ => field_entry.is_stored() && (is_indexed(field_type) || is_fast(field_type)),
+    }
+}
+
+/// Picks up to `sample` live docs spread evenly over the segment, or every live doc if `sample` is 0.
+pub(crate) fn sample_docs(segment: &SegmentReader, sample: usize) -> Vec<DocId> {
+    let max_doc = segment.max_doc();
+    let step = if sample == 0 { 1 } else { (max_doc as usize / sample).max(1) };
+    (0..max_doc)
+        .step_by(step)
+        .filter(|&doc| !segment.is_deleted(doc))
+        .collect()
+}
+
+/// The values the indexer should have produced from a doc's stored copy of `field`.
+fn expected_indexed_values(index: &Index, field: Field, field_type: &FieldType, doc: &Document) -> Result<Vec<TantivyValue>, Error> {
+    let mut values = Vec::new();
+    for value in doc.get_all(field) {
+        match (field_type, value) {
+            (&FieldType::Str(ref options), &Value::Str(ref text)) => {
+                values.extend(tokenize(index, options, text)?.into_iter().map(|token| TantivyValue::Text(token.text)));
+            },
+            _ => values.extend(TantivyValue::from_value(value)),
+        }
+    }
+    Ok(values)
+}
+
+fn stored_values(field: Field, doc: &Document) -> Vec<TantivyValue> {
+    doc.get_all(field).into_iter().filter_map(TantivyValue::from_value).collect()
+}
+
+fn audit_segment(index: &Index, segment: &SegmentReader, sample: usize, audits: &mut HashMap<(Field, &'static str), FieldAudit>) -> Result<(), Error> {
+    let schema = index.schema();
+    let docs = sample_docs(segment, sample);
+    let stored_docs = docs.iter()
+        .map(|&doc| segment.doc(doc))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(TantivyViewerError::TantivyError)?;
+
+    let mut segment_docs = HashMap::new();
+    segment_docs.insert(segment.segment_id(), docs.clone());
+
+    for (idx, field_entry) in schema.fields().iter().enumerate() {
+        if !is_auditable(field_entry) {
+            continue;
+        }
+        let field = Field(idx as u32);
+        let field_type = field_entry.field_type();
+
+        if is_indexed(field_type) {
+            let mut reconstructed = reconstruct(index, field_entry.name(), &segment_docs)?;
+            let reconstructed = reconstructed.remove(&segment.segment_id()).unwrap_or_default();
+            let audit = audits.entry((field, "indexed"))
+                .or_insert_with(|| FieldAudit::new(field_entry.name(), "indexed"));
+            for ((doc, values), stored_doc) in reconstructed.into_iter().zip(stored_docs.iter()) {
+                let expected = expected_indexed_values(index, field, field_type, stored_doc)?;
+                audit.record(segment, doc, expected, values.into_iter().filter_map(|x| x).collect());
+            }
+        }
+
+        if is_fast(field_type) {
+            let audit = audits.entry((field, "fast"))
+                .or_insert_with(|| FieldAudit::new(field_entry.name(), "fast"));
+            for (&doc, stored_doc) in docs.iter().zip(stored_docs.iter()) {
+                let mut values = Vec::new();
+                match *field_type {
+                    FieldType::U64(ref opts) => reconstruct_numeric::<u64>(segment, doc, field, opts.get_fastfield_cardinality(), &mut values)?,
+                    FieldType::I64(ref opts) => reconstruct_numeric::<i64>(segment, doc, field, opts.get_fastfield_cardinality(), &mut values)?,
+                    _ => {},
+                }
+                audit.record(segment, doc, stored_values(field, stored_doc), values.into_iter().filter_map(|x| x).collect());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Compares the stored copy of every stored field with what the inverted index and
+/// fast fields hold for the same docs.
+pub fn audit(index: &Index, sample: usize) -> Result<AuditData, Error> {
+    let searcher = index.searcher();
+    let mut audits = HashMap::new();
+    for segment in searcher.segment_readers() {
+        audit_segment(index, segment, sample, &mut audits)?;
+    }
+
+    let mut fields = audits.into_iter()
+        .map(|(_, mut audit)| {
+            audit.mismatch_rate = if audit.docs_checked == 0 {
+                "-".to_string()
+            } else {
+                format!("{:.2}%", audit.mismatches as f64 * 100.0 / audit.docs_checked as f64)
+            };
+            audit
+        })
+        .collect::<Vec<_>>();
+    fields.sort_by(|x, y| x.field.cmp(&y.field).then_with(|| x.source.cmp(&y.source)));
+
+    Ok(AuditData {
+        sample,
+        fields,
+    })
+}
+
+#[derive(Deserialize)]
+pub struct AuditQuery {
+    sample: Option<usize>,
+}
+
+pub(crate) fn handle_audit(req: (HttpRequest<State>, Query<AuditQuery>)) -> Result<HttpResponse, Error> {
+    let (req, params) = req;
+    let state = req.state();
+    let data = audit(&state.index, params.sample.unwrap_or(1000))?;
+    Ok(state.render_template("audit", &data)?)
+}
+
+pub fn run_audit(index: &Index, sample: usize, format: OutputFormat) -> Result<(), Error> {
+    let data = audit(index, sample)?;
+    match format {
+        OutputFormat::Json => print_json(&data)?,
+        OutputFormat::Table => {
+            let rows = data.fields.iter()
+                .map(|x| vec![
+                    x.field.clone(),
+                    x.source.clone(),
+                    x.docs_checked.to_string(),
+                    x.mismatches.to_string(),
+                    x.mismatch_rate.clone(),
+                ])
+                .collect::<Vec<_>>();
+            print_table(&["field", "source", "docs checked", "mismatches", "mismatch rate"], &rows);
+            let examples = data.fields.iter()
+                .flat_map(|x| x.examples.iter().map(move |example| vec![
+                    x.field.clone(),
+                    x.source.clone(),
+                    example.segment.clone(),
+                    example.doc.to_string(),
+                    example.stored.clone(),
+                    example.rebuilt.clone(),
+                ]))
+                .collect::<Vec<_>>();
+            if !examples.is_empty() {
+                println!();
+                print_table(&["field", "source", "segment", "doc", "stored", "rebuilt"], &examples);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tantivy::schema::SchemaBuilder;
+    use tantivy::schema::FAST;
+    use tantivy::schema::INT_INDEXED;
+    use tantivy::schema::INT_STORED;
+    use tantivy::schema::STORED;
+    use tantivy::schema::TEXT;
+
+    #[test]
+    fn test_audit_agrees_with_what_was_indexed() {
+        let mut schema_builder = SchemaBuilder::default();
+        let title = schema_builder.add_text_field("title", TEXT | STORED);
+        let count = schema_builder.add_u64_field("count", INT_INDEXED | INT_STORED | FAST);
+        let index = Index::create_in_ram(schema_builder.build());
+        let mut writer = index.writer_with_num_threads(1, 40_000_000).unwrap();
+        for i in 0..20u64 {
+            let mut document = Document::default();
+            document.add_text(title, &format!("The Title of doc {}", i));
+            document.add_u64(count, i);
+            writer.add_document(document);
+        }
+        writer.commit().unwrap();
+        index.load_searchers().unwrap();
+
+        let data = audit(&index, 0).unwrap();
+        let fields = data.fields.iter()
+            .map(|x| (&x.field[..], &x.source[..], x.docs_checked, x.mismatches))
+            .collect::<Vec<_>>();
+        assert_eq!(fields, vec![("count", "fast", 20, 0), ("count", "indexed", 20, 0), ("title", "indexed", 20, 0)]);
+    }
+}
@@ -0,0 +1,405 @@
+use std::any::Any;
+use std::cell::Cell;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::process;
+use std::sync::Once;
+use std::sync::ONCE_INIT;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use failure::Error;
+use tantivy::DocId;
+use tantivy::DocSet;
+use tantivy::Index;
+use tantivy::Postings;
+use tantivy::SegmentReader;
+use tantivy::schema::Cardinality;
+use tantivy::schema::Field;
+use tantivy::schema::FieldType;
+use tantivy::schema::Type;
+use tantivy::fastfield::FastValue;
+use tantivy::space_usage::PerFieldSpaceUsage;
+use tantivy::space_usage::SegmentSpaceUsage;
+
+use output::print_json;
+use output::OutputFormat;
+use top_terms::TantivyValue;
+use State;
+
+/// Stop collecting after this many failures; a badly corrupted index would otherwise report one per term.
+const MAX_FAILURES: usize = 1000;
+
+#[derive(Serialize)]
+pub struct CheckFailure {
+    segment: String,
+    field: Option<String>,
+    term: Option<String>,
+    message: String,
+}
+
+#[derive(Serialize)]
+pub struct CheckReport {
+    segments_checked: usize,
+    docs_checked: u64,
+    failures: Vec<CheckFailure>,
+    truncated: bool,
+}
+
+impl CheckReport {
+    fn fail(&mut self, segment: &SegmentReader, field: Option<&str>, term: Option<String>, message: String) {
+        if self.failures.len() >= MAX_FAILURES {
+            self.truncated = true;
+            return;
+        }
+        self.failures.push(CheckFailure {
+            segment: segment.segment_id().uuid_string(),
+            field: field.map(|x| x.to_string()),
+            term,
+            message,
+        });
+    }
+}
+
+fn panic_message(payload: Box<Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        format!("panicked: {}", message)
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        format!("panicked: {}", message)
+    } else {
+        "panicked".to_string()
+    }
+}
+
+thread_local!(static QUIET_PANICS: Cell<bool> = Cell::new(false));
+static INSTALL_QUIET_HOOK: Once = ONCE_INIT;
+
+/// Wraps the panic hook so that panics caught by `guard` don't print to stderr. Other threads,
+/// and this one outside of `guard`, still go through the previous hook.
+fn install_quiet_hook() {
+    INSTALL_QUIET_HOOK.call_once(|| {
+        let previous_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if !QUIET_PANICS.with(|x| x.get()) {
+                previous_hook(info);
+            }
+        }));
+    });
+}
+
+/// Runs `f`, turning both errors and panics into a failure message.
+fn guard<F: FnOnce() -> Result<(), String>>(f: F) -> Result<(), String> {
+    install_quiet_hook();
+    QUIET_PANICS.with(|x| x.set(true));
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    QUIET_PANICS.with(|x| x.set(false));
+    match result {
+        Ok(result) => result,
+        Err(payload) => Err(panic_message(payload)),
+    }
+}
+
+fn term_to_string(key: &[u8], value_type: Type) -> String {
+    match value_type {
+        Type::U64 | Type::I64 if key.len() == 8 => format!("{}", TantivyValue::from_term(key, value_type)),
+        _ => String::from_utf8_lossy(key).into_owned(),
+    }
+}
+
+fn check_postings(report: &mut CheckReport, segment: &SegmentReader, field: Field, name: &str, field_type: &FieldType) {
+    let record_option = match field_type.get_index_record_option() {
+        Some(record_option) => record_option,
+        None => return,
+    };
+    let value_type = field_type.value_type();
+    let max_doc = segment.max_doc();
+    let inverted_index = segment.inverted_index(field);
+    let mut term_stream = inverted_index.terms().stream();
+    let mut previous_key: Option<Vec<u8>> = None;
+    let mut positions = Vec::new();
+
+    while term_stream.advance() {
+        let key = term_stream.key().to_owned();
+        let term = term_to_string(&key, value_type);
+        if let Some(ref previous_key) = previous_key {
+            if key[..] <= previous_key[..] {
+                report.fail(segment, Some(name), Some(term.clone()), format!(
+                    "term dictionary out of order after {:?}", term_to_string(previous_key, value_type)));
+            }
+        }
+
+        let term_info = term_stream.value().clone();
+        let result = guard(|| {
+            let mut postings = inverted_index.read_postings_from_terminfo(&term_info, record_option);
+            let mut num_docs = 0u32;
+            let mut previous_doc: Option<DocId> = None;
+            while postings.advance() {
+                let doc = postings.doc();
+                num_docs += 1;
+                if doc >= max_doc {
+                    return Err(format!("doc {} is beyond max_doc {}", doc, max_doc));
+                }
+                if previous_doc.map(|x| x >= doc).unwrap_or(false) {
+                    return Err(format!("doc {} follows doc {}", doc, previous_doc.unwrap()));
+                }
+                previous_doc = Some(doc);
+                if record_option.has_positions() {
+                    postings.positions(&mut positions);
+                    if positions.len() as u32 != postings.term_freq() {
+                        return Err(format!("doc {} has {} positions but a term freq of {}", doc, positions.len(), postings.term_freq()));
+                    }
+                    if positions.windows(2).any(|w| w[0] > w[1]) {
+                        return Err(format!("positions are not monotonic in doc {}: {:?}", doc, positions));
+                    }
+                }
+            }
+            if num_docs != term_info.doc_freq {
+                return Err(format!("decoded {} docs but doc_freq is {}", num_docs, term_info.doc_freq));
+            }
+            Ok(())
+        });
+        if let Err(message) = result {
+            report.fail(segment, Some(name), Some(term), message);
+        }
+
+        previous_key = Some(key);
+    }
+}
+
+/// Bytes of one part of a field in a composite file, such as the offsets (0) or values (1)
+/// of a multi-valued fast field.
+fn field_part_bytes(usage: &PerFieldSpaceUsage, field: Field, part: usize) -> u64 {
+    usage.fields()
+        .filter(|&(x, _)| *x == field)
+        .filter_map(|(_, usage)| usage.sub_num_bytes().get(part).cloned().and_then(|x| x))
+        .sum::<usize>() as u64
+}
+
+/// Least number of bytes a bitpacked fast field needs for `num_vals` values between `min`
+/// and `max`: the min and amplitude headers, then the packed values.
+fn min_bitpacked_bytes(num_vals: u64, min: u64, max: u64) -> u64 {
+    let num_bits = 64 - (max - min).leading_zeros() as u64;
+    16 + (num_vals * num_bits + 7) / 8
+}
+
+/// Multi-valued and bytes fast fields start with max_doc + 1 offsets into their values.
+fn check_offsets(usage: &PerFieldSpaceUsage, field: Field, max_doc: u64, num_vals: u64) -> Result<(), String> {
+    let needed = min_bitpacked_bytes(max_doc + 1, 0, num_vals);
+    let actual = field_part_bytes(usage, field, 0);
+    if actual < needed {
+        return Err(format!("the offsets take {} bytes, but {} offsets up to {} need at least {}", actual, max_doc + 1, num_vals, needed));
+    }
+    Ok(())
+}
+
+fn check_numeric_fast_field<T: FastValue>(segment: &SegmentReader, usage: &PerFieldSpaceUsage, field: Field, cardinality: Option<Cardinality>) -> Result<(), String> {
+    let max_doc = segment.max_doc();
+    match cardinality {
+        Some(Cardinality::SingleValue) => {
+            let reader = segment.fast_field_reader::<T>(field).map_err(|e| format!("{:?}", e))?;
+            let needed = min_bitpacked_bytes(max_doc as u64, reader.min_value().to_u64(), reader.max_value().to_u64());
+            let actual = field_part_bytes(usage, field, 0);
+            if actual < needed {
+                return Err(format!("the values take {} bytes, but {} values need at least {}", actual, max_doc, needed));
+            }
+            for doc in 0..max_doc {
+                reader.get(doc);
+            }
+        }
+        Some(Cardinality::MultiValues) => {
+            let reader = segment.multi_fast_field_reader::<T>(field).map_err(|e| format!("{:?}", e))?;
+            let mut vals = Vec::new();
+            let mut num_vals = 0u64;
+            for doc in 0..max_doc {
+                reader.get_vals(doc, &mut vals);
+                num_vals += vals.len() as u64;
+            }
+            check_offsets(usage, field, max_doc as u64, num_vals)?;
+        }
+        None => {}
+    }
+    Ok(())
+}
+
+fn check_bytes_fast_field(segment: &SegmentReader, usage: &PerFieldSpaceUsage, field: Field) -> Result<(), String> {
+    let reader = segment.bytes_fast_field_reader(field).map_err(|e| format!("{:?}", e))?;
+    let max_doc = segment.max_doc();
+    let mut num_bytes = 0u64;
+    for doc in 0..max_doc {
+        num_bytes += reader.get_val(doc).len() as u64;
+    }
+    check_offsets(usage, field, max_doc as u64, num_bytes)?;
+    let actual = field_part_bytes(usage, field, 1);
+    if actual != num_bytes {
+        return Err(format!("the docs hold {} bytes, but the values take {}", num_bytes, actual));
+    }
+    Ok(())
+}
+
+/// Compares the size of the field's fast field data with what max_doc values need, then reads
+/// every doc, so that both a short file and one that fails to decode are reported.
+fn check_fast_field(report: &mut CheckReport, segment: &SegmentReader, usage: &SegmentSpaceUsage, field: Field, name: &str, field_type: &FieldType) {
+    let usage = usage.fast_fields();
+    let result = guard(|| {
+        match *field_type {
+            FieldType::U64(ref opts) => check_numeric_fast_field::<u64>(segment, usage, field, opts.get_fastfield_cardinality()),
+            FieldType::I64(ref opts) => check_numeric_fast_field::<i64>(segment, usage, field, opts.get_fastfield_cardinality()),
+            FieldType::Bytes => check_bytes_fast_field(segment, usage, field),
+            FieldType::Str(_) | FieldType::HierarchicalFacet => Ok(()),
+        }
+    });
+    if let Err(message) = result {
+        report.fail(segment, Some(name), None, format!("fast field does not cover max_doc {}: {}", segment.max_doc(), message));
+    }
+}
+
+/// Fieldnorms are one byte per doc, so the field's fieldnorm data must be exactly max_doc bytes.
+fn check_fieldnorms(report: &mut CheckReport, segment: &SegmentReader, usage: &SegmentSpaceUsage, field: Field, name: &str, field_type: &FieldType) {
+    if field_type.get_index_record_option().is_none() {
+        return;
+    }
+    let max_doc = segment.max_doc();
+    let num_bytes = field_part_bytes(usage.fieldnorms(), field, 0);
+    if num_bytes != max_doc as u64 {
+        report.fail(segment, Some(name), None, format!("fieldnorms do not cover max_doc {}: they take {} bytes", max_doc, num_bytes));
+    }
+}
+
+/// Reads every stored doc. Docs of a corrupt block all fail the same way, so a run of failing
+/// docs is reported once, and checking carries on with the blocks after it.
+fn check_store(report: &mut CheckReport, segment: &SegmentReader) {
+    let mut failing: Option<(DocId, String)> = None;
+    for doc in 0..segment.max_doc() {
+        let result = guard(|| segment.doc(doc).map(|_| ()).map_err(|e| format!("{:?}", e)));
+        match result {
+            Err(message) => {
+                if failing.is_none() {
+                    failing = Some((doc, message));
+                }
+            }
+            Ok(()) => {
+                if let Some((first_doc, message)) = failing.take() {
+                    report.fail(segment, None, None, format!("doc store does not decompress for docs {} to {}: {}", first_doc, doc - 1, message));
+                }
+            }
+        }
+    }
+    if let Some((first_doc, message)) = failing {
+        report.fail(segment, None, None, format!("doc store does not decompress for docs {} to {}: {}", first_doc, segment.max_doc() - 1, message));
+    }
+}
+
+pub fn check(index: &Index) -> CheckReport {
+    let schema = index.schema();
+    let searcher = index.searcher();
+    let mut report = CheckReport {
+        segments_checked: 0,
+        docs_checked: 0,
+        failures: Vec::new(),
+        truncated: false,
+    };
+
+    for segment in searcher.segment_readers() {
+        let usage = segment.space_usage();
+        for (idx, field_entry) in schema.fields().iter().enumerate() {
+            let field = Field(idx as u32);
+            let name = field_entry.name();
+            let field_type = field_entry.field_type();
+            check_postings(&mut report, segment, field, name, field_type);
+            check_fast_field(&mut report, segment, &usage, field, name, field_type);
+            check_fieldnorms(&mut report, segment, &usage, field, name, field_type);
+        }
+        check_store(&mut report, segment);
+        report.segments_checked += 1;
+        report.docs_checked += segment.max_doc() as u64;
+    }
+
+    report
+}
+
+/// Entry point for the `check` subcommand. Exits with a non-zero status when problems are found.
+pub fn run_check(index: &Index, format: OutputFormat) -> Result<(), Error> {
+    let report = check(index);
+    match format {
+        OutputFormat::Json => print_json(&report)?,
+        OutputFormat::Table => {
+            for failure in report.failures.iter() {
+                println!(
+                    "segment {} field {} term {}: {}",
+                    failure.segment,
+                    failure.field.as_ref().map(|x| &x[..]).unwrap_or("-"),
+                    failure.term.as_ref().map(|x| &x[..]).unwrap_or("-"),
+                    failure.message,
+                );
+            }
+            if report.truncated {
+                println!("[...Truncated after {} failures...]", MAX_FAILURES);
+            }
+            println!("Checked {} segments, {} docs: {} failures", report.segments_checked, report.docs_checked, report.failures.len());
+        }
+    }
+    if !report.failures.is_empty() {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+pub(crate) fn handle_check(req: HttpRequest<State>) -> Result<HttpResponse, Error> {
+    let state = req.state();
+    let report = check(&state.index);
+    Ok(state.render_template("check", &report)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tantivy::Document;
+    use tantivy::schema::IntOptions;
+    use tantivy::schema::SchemaBuilder;
+    use tantivy::schema::FAST;
+    use tantivy::schema::INT_INDEXED;
+    use tantivy::schema::STORED;
+    use tantivy::schema::TEXT;
+
+    #[test]
+    fn test_min_bitpacked_bytes() {
+        assert_eq!(min_bitpacked_bytes(10, 5, 5), 16);
+        assert_eq!(min_bitpacked_bytes(8, 0, 1), 17);
+        assert_eq!(min_bitpacked_bytes(3, 0, 255), 19);
+        assert_eq!(min_bitpacked_bytes(1, 0, u64::max_value()), 24);
+    }
+
+    #[test]
+    fn test_guard_catches_errors_and_panics() {
+        assert_eq!(guard(|| Ok(())), Ok(()));
+        assert_eq!(guard(|| Err("broken".to_string())), Err("broken".to_string()));
+        assert_eq!(guard(|| panic!("boom")), Err("panicked: boom".to_string()));
+        assert!(!QUIET_PANICS.with(|x| x.get()));
+    }
+
+    #[test]
+    fn test_check_passes_a_healthy_index() {
+        let mut schema_builder = SchemaBuilder::default();
+        let title = schema_builder.add_text_field("title", TEXT | STORED);
+        let count = schema_builder.add_u64_field("count", INT_INDEXED | FAST);
+        let tags = schema_builder.add_u64_field("tags", IntOptions::default().set_fast(Cardinality::MultiValues));
+        let index = Index::create_in_ram(schema_builder.build());
+        let mut writer = index.writer_with_num_threads(1, 40_000_000).unwrap();
+        for i in 0..100u64 {
+            let mut document = Document::default();
+            document.add_text(title, &format!("doc number {}", i));
+            document.add_u64(count, i * 1000);
+            for tag in 0..(i % 4) {
+                document.add_u64(tags, tag);
+            }
+            writer.add_document(document);
+        }
+        writer.commit().unwrap();
+        index.load_searchers().unwrap();
+
+        let report = check(&index);
+        let messages = report.failures.iter().map(|x| x.message.clone()).collect::<Vec<_>>();
+        assert_eq!(messages, Vec::<String>::new());
+        assert_eq!(report.segments_checked, 1);
+        assert_eq!(report.docs_checked, 100);
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use actix_web::Query;
+use failure::Error;
+use tantivy::DocId;
+use tantivy::Index;
+use tantivy::SegmentId;
+use tantivy::schema::Type;
+
+use output::print_json;
+use output::print_table;
+use output::OutputFormat;
+use reconstruct::reconstruct;
+use segments::disambiguation_page;
+use segments::find_segment;
+use segments::resolve_segment;
+use segments::SegmentMatch;
+use stringify_values;
+use State;
+use TantivyViewerError;
+
+#[derive(Serialize)]
+pub struct FieldComparison {
+    field: String,
+    a: String,
+    b: String,
+    differs: bool,
+    only_in_a: Vec<String>,
+    only_in_b: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct DocComparison {
+    segment_a: String,
+    doc_a: DocId,
+    segment_b: String,
+    doc_b: DocId,
+    fields: Vec<FieldComparison>,
+}
+
+fn term_set(values: &[Option<String>]) -> BTreeSet<String> {
+    values.iter().filter_map(|x| x.clone()).collect()
+}
+
+/// Reconstructs every field of two docs and lines them up field by field.
+///
+/// For indexed text fields, the terms each doc has that the other lacks are listed too,
+/// which is usually what explains one doc matching a query and the other not.
+pub fn compare(index: &Index, a: (SegmentId, DocId), b: (SegmentId, DocId)) -> Result<DocComparison, Error> {
+    let schema = index.schema();
+    // Both docs are reconstructed together, so each field's terms are only walked once.
+    let mut docs = HashMap::new();
+    docs.entry(a.0).or_insert_with(Vec::new).push(a.1);
+    docs.entry(b.0).or_insert_with(Vec::new).push(b.1);
+    for segment_docs in docs.values_mut() {
+        segment_docs.sort();
+    }
+    let mut fields = Vec::new();
+    for field_entry in schema.fields() {
+        let name = field_entry.name();
+        let reconstructed = reconstruct(index, name, &docs)?;
+        let values_of = |(segment, doc): (SegmentId, DocId)| {
+            reconstructed.get(&segment)
+                .and_then(|x| x.iter().find(|&&(x, _)| x == doc))
+                .map(|x| x.1.clone())
+                .unwrap_or_default()
+        };
+        let values_a = values_of(a);
+        let values_b = values_of(b);
+        let terms_a = values_a.iter().map(|x| x.as_ref().map(|x| format!("{}", x))).collect::<Vec<_>>();
+        let terms_b = values_b.iter().map(|x| x.as_ref().map(|x| format!("{}", x))).collect::<Vec<_>>();
+
+        let is_text = match field_entry.field_type().value_type() {
+            Type::Str => field_entry.field_type().get_index_record_option().is_some(),
+            _ => false,
+        };
+        let (only_in_a, only_in_b) = if is_text {
+            let set_a = term_set(&terms_a);
+            let set_b = term_set(&terms_b);
+            (
+                set_a.difference(&set_b).cloned().collect(),
+                set_b.difference(&set_a).cloned().collect(),
+            )
+        } else {
+            (Vec::new(), Vec::new())
+        };
+
+        fields.push(FieldComparison {
+            field: name.to_string(),
+            a: stringify_values(values_a),
+            b: stringify_values(values_b),
+            differs: terms_a != terms_b,
+            only_in_a,
+            only_in_b,
+        });
+    }
+    fields.sort_by(|x, y| x.field.cmp(&y.field));
+
+    Ok(DocComparison {
+        segment_a: a.0.uuid_string(),
+        doc_a: a.1,
+        segment_b: b.0.uuid_string(),
+        doc_b: b.1,
+        fields,
+    })
+}
+
+#[derive(Deserialize)]
+pub struct CompareQuery {
+    segment_a: Option<String>,
+    doc_a: Option<DocId>,
+    segment_b: Option<String>,
+    doc_b: Option<DocId>,
+}
+
+#[derive(Serialize)]
+struct ComparePage {
+    segment_a: String,
+    doc_a: String,
+    segment_b: String,
+    doc_b: String,
+    comparison: Option<DocComparison>,
+}
+
+pub(crate) fn handle_compare(req: (HttpRequest<State>, Query<CompareQuery>)) -> Result<HttpResponse, Error> {
+    let (req, params) = req;
+    let state = req.state();
+    let mut page = ComparePage {
+        segment_a: params.segment_a.clone().unwrap_or_default(),
+        doc_a: params.doc_a.map(|x| x.to_string()).unwrap_or_default(),
+        segment_b: params.segment_b.clone().unwrap_or_default(),
+        doc_b: params.doc_b.map(|x| x.to_string()).unwrap_or_default(),
+        comparison: None,
+    };
+
+    let (segment_a, doc_a, segment_b, doc_b) = match (&params.segment_a, params.doc_a, &params.segment_b, params.doc_b) {
+        (&Some(ref segment_a), Some(doc_a), &Some(ref segment_b), Some(doc_b)) => (segment_a, doc_a, segment_b, doc_b),
+        _ => return Ok(state.render_template("compare", &page)?),
+    };
+
+    let mut resolved = Vec::new();
+    for &(param, prefix) in [("segment_a", segment_a), ("segment_b", segment_b)].iter() {
+        match find_segment(&state.index, prefix).map_err(TantivyViewerError::TantivyError)? {
+            SegmentMatch::Found(segment) => resolved.push(segment),
+            SegmentMatch::Ambiguous(candidates) => return Ok(disambiguation_page(&req, param, prefix, candidates)?),
+            SegmentMatch::NotFound => return Err(TantivyViewerError::SegmentNotFoundError.into()),
+        }
+    }
+
+    page.comparison = Some(compare(&state.index, (resolved[0], doc_a), (resolved[1], doc_b))?);
+    Ok(state.render_template("compare", &page)?)
+}
+
+pub fn run_compare(index: &Index, a: (&str, DocId), b: (&str, DocId), format: OutputFormat) -> Result<(), Error> {
+    let a = (resolve_segment(index, a.0)?, a.1);
+    let b = (resolve_segment(index, b.0)?, b.1);
+    let data = compare(index, a, b)?;
+    match format {
+        OutputFormat::Json => print_json(&data)?,
+        OutputFormat::Table => {
+            let rows = data.fields.iter()
+                .map(|x| vec![
+                    x.field.clone(),
+                    if x.differs { "yes" } else { "" }.to_string(),
+                    x.a.clone(),
+                    x.b.clone(),
+                    x.only_in_a.join(" "),
+                    x.only_in_b.join(" "),
+                ])
+                .collect::<Vec<_>>();
+            print_table(&["field", "differs", "a", "b", "only in a", "only in b"], &rows);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tantivy::Document;
+    use tantivy::schema::SchemaBuilder;
+    use tantivy::schema::INT_INDEXED;
+    use tantivy::schema::STRING;
+    use tantivy::schema::TEXT;
+
+    #[test]
+    fn test_compare() {
+        let mut schema_builder = SchemaBuilder::default();
+        let title = schema_builder.add_text_field("title", TEXT);
+        let id = schema_builder.add_text_field("id", STRING);
+        let count = schema_builder.add_u64_field("count", INT_INDEXED);
+        let index = Index::create_in_ram(schema_builder.build());
+        let mut writer = index.writer_with_num_threads(1, 40_000_000).unwrap();
+        for &(title_value, count_value) in &[("red apple", 1u64), ("green apple", 2)] {
+            let mut document = Document::default();
+            document.add_text(title, title_value);
+            document.add_text(id, "same");
+            document.add_u64(count, count_value);
+            writer.add_document(document);
+        }
+        writer.commit().unwrap();
+        index.load_searchers().unwrap();
+
+        let segment = index.searchable_segment_ids().unwrap()[0];
+        let comparison = compare(&index, (segment, 1), (segment, 0)).unwrap();
+        let fields = comparison.fields.iter().map(|x| (&x.field[..], x.differs)).collect::<Vec<_>>();
+        assert_eq!(fields, vec![("count", true), ("id", false), ("title", true)]);
+        let title_comparison = &comparison.fields[2];
+        assert_eq!(title_comparison.only_in_a, vec!["green".to_string()]);
+        assert_eq!(title_comparison.only_in_b, vec!["red".to_string()]);
+        // Numeric fields have no term sets to compare.
+        assert!(comparison.fields[0].only_in_a.is_empty());
+    }
+}
@@ -0,0 +1,234 @@
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use failure::Error;
+use tantivy::DocId;
+use tantivy::DocSet;
+use tantivy::Index;
+use tantivy::SegmentReader;
+use tantivy::fastfield::FastValue;
+use tantivy::schema::Cardinality;
+use tantivy::schema::Field;
+use tantivy::schema::FieldType;
+use tantivy::schema::IndexRecordOption;
+use tantivy::schema::IntOptions;
+
+use output::print_json;
+use output::print_table;
+use output::OutputFormat;
+
+#[derive(Debug, Serialize)]
+pub struct FieldCoverage {
+    live_docs: u64,
+    docs_with_value: u64,
+    percent: String,
+    min_values: u32,
+    mean_values: String,
+    max_values: u32,
+}
+
+/// A single-valued fast field holds a value, 0 by default, for every doc whether or not one was
+/// added, so its values can only be counted from the postings when it is also indexed.
+fn fast_value_counts<T: FastValue>(segment: &SegmentReader, field: Field, options: &IntOptions) -> Result<Option<Vec<u32>>, Error> {
+    let max_doc = segment.max_doc();
+    Ok(match options.get_fastfield_cardinality() {
+        Some(Cardinality::SingleValue) if options.is_indexed() => Some(postings_counts(segment, field)),
+        Some(Cardinality::SingleValue) => None,
+        Some(Cardinality::MultiValues) => {
+            let reader = segment.multi_fast_field_reader::<T>(field)?;
+            let mut vals = Vec::new();
+            let mut counts = Vec::with_capacity(max_doc as usize);
+            for doc in 0..max_doc {
+                reader.get_vals(doc, &mut vals);
+                counts.push(vals.len() as u32);
+            }
+            Some(counts)
+        }
+        None => None,
+    })
+}
+
+/// Counts the distinct terms each doc has in `field` by walking every posting list.
+fn postings_counts(segment: &SegmentReader, field: Field) -> Vec<u32> {
+    let mut counts = vec![0; segment.max_doc() as usize];
+    let inverted_index = segment.inverted_index(field);
+    let mut term_stream = inverted_index.terms().stream();
+    while term_stream.advance() {
+        let mut postings = inverted_index.read_postings_from_terminfo(term_stream.value(), IndexRecordOption::Basic);
+        while postings.advance() {
+            counts[postings.doc() as usize] += 1;
+        }
+    }
+    counts
+}
+
+fn fieldnorm_counts(segment: &SegmentReader, field: Field) -> Vec<u32> {
+    let reader = segment.get_fieldnorms_reader(field);
+    (0..segment.max_doc()).map(|doc| reader.fieldnorm(doc)).collect()
+}
+
+/// Number of values each doc in the segment has for `field`, or `None` if it can't be
+/// counted without the doc store.
+fn value_counts(segment: &SegmentReader, field: Field, field_type: &FieldType) -> Result<Option<Vec<u32>>, Error> {
+    Ok(match *field_type {
+        FieldType::Str(_) if field_type.get_index_record_option().is_some() => Some(fieldnorm_counts(segment, field)),
+        FieldType::U64(ref opts) if opts.is_fast() => fast_value_counts::<u64>(segment, field, opts)?,
+        FieldType::I64(ref opts) if opts.is_fast() => fast_value_counts::<i64>(segment, field, opts)?,
+        FieldType::Bytes => {
+            let reader = segment.bytes_fast_field_reader(field)?;
+            Some((0..segment.max_doc()).map(|doc| if reader.get_val(doc).is_empty() { 0 } else { 1 }).collect())
+        }
+        _ if field_type.get_index_record_option().is_some() => Some(postings_counts(segment, field)),
+        _ => None,
+    })
+}
+
+/// How many live docs have at least one value for each field, and how many values they have.
+pub fn field_coverage(index: &Index) -> Result<HashMap<String, FieldCoverage>, Error> {
+    let schema = index.schema();
+    let searcher = index.searcher();
+    let mut result = HashMap::new();
+
+    for (idx, field_entry) in schema.fields().iter().enumerate() {
+        let field = Field(idx as u32);
+        let mut live_docs = 0u64;
+        let mut docs_with_value = 0u64;
+        let mut total_values = 0u64;
+        let mut min_values = None;
+        let mut max_values = 0u32;
+        let mut countable = true;
+
+        for segment in searcher.segment_readers() {
+            let counts = match value_counts(segment, field, field_entry.field_type())? {
+                Some(counts) => counts,
+                None => {
+                    countable = false;
+                    break;
+                }
+            };
+            for (doc, &count) in counts.iter().enumerate() {
+                if segment.is_deleted(doc as DocId) {
+                    continue;
+                }
+                live_docs += 1;
+                if count > 0 {
+                    docs_with_value += 1;
+                }
+                total_values += count as u64;
+                min_values = Some(min_values.map(|x: u32| x.min(count)).unwrap_or(count));
+                max_values = max_values.max(count);
+            }
+        }
+
+        if !countable {
+            continue;
+        }
+
+        let (percent, mean_values) = if live_docs == 0 {
+            ("-".to_string(), "-".to_string())
+        } else {
+            (
+                format!("{:.1}%", docs_with_value as f64 * 100.0 / live_docs as f64),
+                format!("{:.2}", total_values as f64 / live_docs as f64),
+            )
+        };
+
+        result.insert(field_entry.name().to_string(), FieldCoverage {
+            live_docs,
+            docs_with_value,
+            percent,
+            min_values: min_values.unwrap_or(0),
+            mean_values,
+            max_values,
+        });
+    }
+
+    Ok(result)
+}
+
+pub fn run_coverage(index: &Index, format: OutputFormat) -> Result<(), Error> {
+    let mut coverage = field_coverage(index)?;
+    // Fields that can't be counted are listed too, rather than left out.
+    let coverage = index.schema()
+        .fields()
+        .iter()
+        .map(|x| (x.name().to_string(), coverage.remove(x.name())))
+        .collect::<BTreeMap<_, _>>();
+    match format {
+        OutputFormat::Json => print_json(&coverage)?,
+        OutputFormat::Table => {
+            let rows = coverage.iter()
+                .map(|(field, x)| match *x {
+                    Some(ref x) => vec![
+                        field.clone(),
+                        x.docs_with_value.to_string(),
+                        x.live_docs.to_string(),
+                        x.percent.clone(),
+                        x.min_values.to_string(),
+                        x.mean_values.clone(),
+                        x.max_values.to_string(),
+                    ],
+                    None => vec![field.clone(), "unknown".to_string()],
+                })
+                .collect::<Vec<_>>();
+            print_table(&["field", "docs with a value", "live docs", "coverage", "min values", "mean values", "max values"], &rows);
+            if coverage.values().any(|x| x.is_none()) {
+                println!();
+                println!("Coverage is unknown for fields that are only stored, and for single-valued fast fields that aren't indexed, which hold a value for every doc.");
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tantivy::Document;
+    use tantivy::schema::SchemaBuilder;
+    use tantivy::schema::FAST;
+    use tantivy::schema::INT_INDEXED;
+    use tantivy::schema::TEXT;
+
+    #[test]
+    fn test_field_coverage() {
+        let mut schema_builder = SchemaBuilder::default();
+        let title = schema_builder.add_text_field("title", TEXT);
+        let count = schema_builder.add_u64_field("count", INT_INDEXED | FAST);
+        let fast_only = schema_builder.add_u64_field("fast_only", FAST);
+        let tags = schema_builder.add_u64_field("tags", IntOptions::default().set_fast(Cardinality::MultiValues));
+        let index = Index::create_in_ram(schema_builder.build());
+        let mut writer = index.writer_with_num_threads(1, 40_000_000).unwrap();
+        let docs: Vec<(Option<&str>, Option<u64>, Vec<u64>)> = vec![
+            (Some("a b"), Some(1), vec![1, 2]),
+            (None, Some(2), vec![]),
+            (Some("c"), None, vec![3]),
+            (Some("d e f"), Some(3), vec![]),
+        ];
+        for (title_value, count_value, tag_values) in docs {
+            let mut document = Document::default();
+            if let Some(text) = title_value {
+                document.add_text(title, text);
+            }
+            if let Some(value) = count_value {
+                document.add_u64(count, value);
+                document.add_u64(fast_only, value);
+            }
+            for value in tag_values {
+                document.add_u64(tags, value);
+            }
+            writer.add_document(document);
+        }
+        writer.commit().unwrap();
+        index.load_searchers().unwrap();
+
+        let coverage = field_coverage(&index).unwrap();
+        let summary = |name: &str| coverage.get(name).map(|x| (x.live_docs, x.docs_with_value, x.min_values, x.max_values));
+        assert_eq!(summary("title"), Some((4, 3, 0, 3)));
+        assert_eq!(summary("count"), Some((4, 3, 0, 1)));
+        assert_eq!(summary("tags"), Some((4, 2, 0, 2)));
+        // A single-valued fast field that isn't indexed reads 0 for docs without a value.
+        assert_eq!(summary("fast_only"), None);
+        assert_eq!(coverage["title"].percent, "75.0%");
+        assert_eq!(coverage["tags"].mean_values, "0.75");
+    }
+}
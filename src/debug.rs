@@ -5,7 +5,10 @@ use actix_web::Query;
 use tantivy::query::QueryParser;
 use tantivy::query::BooleanQuery;
 use tantivy::query::Occur;
+use failure::Error;
 
+use output::print_json;
+use output::OutputFormat;
 use TantivyViewerError;
 use child_queries;
 use query_to_string;
@@ -86,3 +89,30 @@ pub(crate) fn handle_debug(req: (HttpRequest<State>, Query<DebugQuery>)) -> Resu
 
     state.render_template("debug", &data)
 }
+
+fn print_tree(tree: &DebugTree, depth: usize) {
+    println!("{:>10}  {}{}", tree.count, "  ".repeat(depth), tree.query_string);
+    for child in tree.children.iter() {
+        print_tree(child, depth + 1);
+    }
+}
+
+pub fn run_debug(index: &Index, raw_query: &str, raw_salient_docs_query: Option<&str>, format: OutputFormat) -> Result<(), Error> {
+    let query_parser = QueryParser::for_index(index, vec![]);
+    let query = query_parser.parse_query(raw_query).map_err(TantivyViewerError::QueryParserError)?;
+    let salient_docs_query = raw_salient_docs_query
+        .filter(|x| !x.is_empty())
+        .map(|q| query_parser.parse_query(q).map_err(TantivyViewerError::QueryParserError))
+        .transpose()?;
+
+    let mut tree = debug_query(index, &*query, &salient_docs_query)?;
+    tree.salient_docs_query_string = raw_salient_docs_query.map(|x| x.to_string());
+    match format {
+        OutputFormat::Json => print_json(&tree)?,
+        OutputFormat::Table => {
+            println!("{:>10}  {}", "count", "query");
+            print_tree(&tree, 0);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,262 @@
+use std::fs;
+use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+use failure::err_msg;
+use failure::Error;
+use serde_json;
+use tantivy::DocId;
+use tantivy::Document;
+use tantivy::Index;
+use tantivy::SegmentReader;
+use tantivy::schema::Facet;
+use tantivy::schema::Field;
+use tantivy::schema::FieldType;
+use tantivy::schema::Schema;
+
+use export::DocBatches;
+use top_terms::TantivyValue;
+use TantivyViewerError;
+
+/// Memory given to the index writer when building a new index.
+pub const WRITER_HEAP_BYTES: usize = 200_000_000;
+
+pub const DOCUMENTS_FILE: &str = "documents.ndjson";
+pub const SCHEMA_FILE: &str = "schema.json";
+
+/// Whether a field is worth reconstructing when it isn't stored.
+fn is_reconstructable(field_type: &FieldType) -> bool {
+    match *field_type {
+        FieldType::Str(_) => field_type.get_index_record_option().is_some(),
+        FieldType::U64(ref options) | FieldType::I64(ref options) => options.is_indexed() || options.is_fast(),
+        FieldType::Bytes => true,
+        FieldType::HierarchicalFacet => true,
+    }
+}
+
+/// Adds reconstructed values back onto a document as the field's own type.
+///
+/// Tokens of a text field can't be told apart from separate values, so they are joined
+/// with spaces into a single value, except for the `raw` tokenizer where each token is
+/// a whole value.
+///
+/// Every ancestor of a facet is indexed alongside it, so only the facets that aren't an
+/// ancestor of another one are added back.
+fn add_reconstructed(doc: &mut Document, field: Field, field_type: &FieldType, values: Vec<Option<TantivyValue>>) {
+    let values = values.into_iter().filter_map(|x| x).collect::<Vec<_>>();
+    if let FieldType::Str(ref options) = *field_type {
+        let raw = options.get_indexing_options().map(|x| x.tokenizer() == "raw").unwrap_or(false);
+        let texts = values.iter().map(|x| format!("{}", x)).collect::<Vec<_>>();
+        if raw {
+            for text in texts {
+                doc.add_text(field, &text);
+            }
+        } else if !texts.is_empty() {
+            doc.add_text(field, &texts.join(" "));
+        }
+        return;
+    }
+    if let FieldType::HierarchicalFacet = *field_type {
+        let paths = values.iter().map(|x| format!("{}", x)).collect::<Vec<_>>();
+        for path in &paths {
+            let is_ancestor = paths.iter().any(|other| other != path && (path == "/" || other.starts_with(&format!("{}/", path))));
+            if !is_ancestor {
+                doc.add_facet(field, Facet::from_text(path));
+            }
+        }
+        return;
+    }
+    for value in values {
+        match value {
+            TantivyValue::U64(value) => doc.add_u64(field, value),
+            TantivyValue::I64(value) => doc.add_i64(field, value),
+            TantivyValue::Bytes(value) => doc.add_bytes(field, value),
+            TantivyValue::Text(ref value) => doc.add_text(field, value),
+        }
+    }
+}
+
+/// Rebuilds every live doc, or every live doc matching `query`, segment by segment.
+///
+/// Stored fields come straight from the doc store, and the remaining fields are
+/// reconstructed from the inverted index or fast fields.
+pub(crate) fn for_each_document<F>(index: &Index, query: Option<&::tantivy::query::Query>, mut f: F) -> Result<(), Error>
+    where F: FnMut(&SegmentReader, DocId, Document) -> Result<(), Error> {
+    let schema = index.schema();
+    let reconstructed = schema.fields()
+        .iter()
+        .enumerate()
+        .filter(|&(_, field_entry)| !field_entry.is_stored() && is_reconstructable(field_entry.field_type()))
+        .map(|(idx, field_entry)| (Field(idx as u32), field_entry.field_type().clone(), field_entry.name().to_string()))
+        .collect::<Vec<_>>();
+    let field_names = reconstructed.iter().map(|x| x.2.clone()).collect::<Vec<_>>();
+
+    let mut batches = DocBatches::new(index, query, false, &field_names)?;
+    while let Some(batch) = batches.next_batch()? {
+        let mut values = batch.values.into_iter().map(|x| x.into_iter()).collect::<Vec<_>>();
+        for (doc, _score) in batch.docs {
+            let stored = batch.segment.doc(doc).map_err(TantivyViewerError::TantivyError)?;
+            let mut document = Document::default();
+            for field_value in stored.field_values() {
+                document.add(field_value.clone());
+            }
+            for (&(field, ref field_type, _), field_values) in reconstructed.iter().zip(values.iter_mut()) {
+                add_reconstructed(&mut document, field, field_type, field_values.next().unwrap_or_default());
+            }
+            f(&batch.segment, doc, document)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes every live doc as one line of NDJSON, alongside the schema, into `output_dir`.
+pub fn dump(index: &Index, output_dir: &Path) -> Result<u64, Error> {
+    fs::create_dir_all(output_dir)?;
+    let schema = index.schema();
+    let schema_json = serde_json::to_string_pretty(&schema).map_err(|_e| TantivyViewerError::JsonSerializationError)?;
+    fs::write(output_dir.join(SCHEMA_FILE), schema_json)?;
+
+    let mut output = BufWriter::new(File::create(output_dir.join(DOCUMENTS_FILE))?);
+    let mut num_docs = 0;
+    for_each_document(index, None, |_segment, _doc, document| {
+        writeln!(output, "{}", schema.to_json(&document))?;
+        num_docs += 1;
+        Ok(())
+    })?;
+    output.flush()?;
+    Ok(num_docs)
+}
+
+pub fn read_schema(dump_dir: &Path) -> Result<Schema, Error> {
+    let schema_file = File::open(dump_dir.join(SCHEMA_FILE))?;
+    Ok(serde_json::from_reader(schema_file).map_err(|_e| err_msg("Could not parse the dumped schema"))?)
+}
+
+/// Builds a new index at `index_dir` from a dump written by `dump`.
+pub fn import(dump_dir: &Path, index_dir: &Path) -> Result<u64, Error> {
+    let schema = read_schema(dump_dir)?;
+    fs::create_dir_all(index_dir)?;
+    let index = Index::create_in_dir(index_dir, schema.clone()).map_err(TantivyViewerError::TantivyError)?;
+    let mut writer = index.writer(WRITER_HEAP_BYTES).map_err(TantivyViewerError::TantivyError)?;
+
+    let documents = BufReader::new(File::open(dump_dir.join(DOCUMENTS_FILE))?);
+    let mut num_docs = 0;
+    for (line_number, line) in documents.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let document = schema.parse_document(&line)
+            .map_err(|e| err_msg(format!("Line {}: {:?}", line_number + 1, e)))?;
+        writer.add_document(document);
+        num_docs += 1;
+    }
+    writer.commit().map_err(TantivyViewerError::TantivyError)?;
+    writer.wait_merging_threads().map_err(TantivyViewerError::TantivyError)?;
+    Ok(num_docs)
+}
+
+pub fn run_dump(index: &Index, output_dir: &Path) -> Result<(), Error> {
+    let num_docs = dump(index, output_dir)?;
+    println!("Dumped {} docs to {}", num_docs, output_dir.display());
+    Ok(())
+}
+
+pub fn run_import(dump_dir: &Path, index_dir: &Path) -> Result<(), Error> {
+    let num_docs = import(dump_dir, index_dir)?;
+    println!("Imported {} docs into {}", num_docs, index_dir.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+    use tantivy::Term;
+    use tantivy::schema::SchemaBuilder;
+    use tantivy::schema::TextFieldIndexing;
+    use tantivy::schema::TextOptions;
+    use tantivy::schema::INT_INDEXED;
+    use tantivy::schema::STORED;
+    use tantivy::schema::STRING;
+    use tantivy::schema::TEXT;
+
+    fn texts(doc: &Document, field: Field) -> Vec<String> {
+        doc.get_all(field).into_iter().map(|x| match *x {
+            ::tantivy::schema::Value::Str(ref text) => text.clone(),
+            ::tantivy::schema::Value::Facet(ref facet) => format!("{}", facet),
+            _ => panic!("unexpected value"),
+        }).collect()
+    }
+
+    fn text(value: &str) -> Option<TantivyValue> {
+        Some(TantivyValue::Text(value.to_string()))
+    }
+
+    #[test]
+    fn test_add_reconstructed_text() {
+        let raw_options = TextOptions::default().set_indexing_options(TextFieldIndexing::default().set_tokenizer("raw"));
+        let mut doc = Document::default();
+        add_reconstructed(&mut doc, Field(0), &FieldType::Str(TEXT), vec![text("quick"), None, text("fox")]);
+        add_reconstructed(&mut doc, Field(1), &FieldType::Str(raw_options), vec![text("New York"), text("Paris")]);
+        add_reconstructed(&mut doc, Field(2), &FieldType::Str(TEXT), vec![None]);
+        assert_eq!(texts(&doc, Field(0)), vec!["quick fox"]);
+        assert_eq!(texts(&doc, Field(1)), vec!["New York", "Paris"]);
+        assert!(texts(&doc, Field(2)).is_empty());
+    }
+
+    #[test]
+    fn test_add_reconstructed_facets_keeps_the_deepest_paths() {
+        let mut doc = Document::default();
+        let values = vec![text("/"), text("/a"), text("/a/b"), text("/ab"), text("/c")];
+        add_reconstructed(&mut doc, Field(0), &FieldType::HierarchicalFacet, values);
+        assert_eq!(texts(&doc, Field(0)), vec!["/a/b", "/ab", "/c"]);
+    }
+
+    fn sorted_documents(dump_dir: &Path) -> Vec<String> {
+        let mut lines = fs::read_to_string(dump_dir.join(DOCUMENTS_FILE)).unwrap()
+            .lines()
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>();
+        lines.sort();
+        lines
+    }
+
+    #[test]
+    fn test_dump_and_import_round_trip() {
+        let mut schema_builder = SchemaBuilder::default();
+        let id = schema_builder.add_text_field("id", STRING | STORED);
+        let body = schema_builder.add_text_field("body", TEXT);
+        let count = schema_builder.add_u64_field("count", INT_INDEXED);
+        let index = Index::create_in_ram(schema_builder.build());
+        let mut writer = index.writer_with_num_threads(1, 40_000_000).unwrap();
+        for i in 0..5u64 {
+            let mut document = Document::default();
+            document.add_text(id, &format!("doc{}", i));
+            document.add_text(body, &format!("the quick fox number {}", i));
+            document.add_u64(count, i);
+            writer.add_document(document);
+        }
+        writer.delete_term(Term::from_field_text(id, "doc3"));
+        writer.commit().unwrap();
+        index.load_searchers().unwrap();
+
+        let root = env::temp_dir().join(format!("tantivy-viewer-dump-{}", process::id()));
+        let _ = fs::remove_dir_all(&root);
+        assert_eq!(dump(&index, &root.join("dump")).unwrap(), 4);
+        assert_eq!(import(&root.join("dump"), &root.join("imported")).unwrap(), 4);
+        let imported = Index::open_in_dir(root.join("imported")).unwrap();
+        assert_eq!(imported.searcher().num_docs(), 4);
+        dump(&imported, &root.join("dump_again")).unwrap();
+
+        let original = sorted_documents(&root.join("dump"));
+        let again = sorted_documents(&root.join("dump_again"));
+        fs::remove_dir_all(&root).unwrap();
+        assert_eq!(original, again);
+        assert!(original[0].contains("the quick fox number 0"));
+    }
+}
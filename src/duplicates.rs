@@ -0,0 +1,188 @@
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use actix_web::Query;
+use failure::err_msg;
+use failure::Error;
+use tantivy::DocId;
+use tantivy::DocSet;
+use tantivy::Index;
+use tantivy::schema::IndexRecordOption;
+
+use output::print_json;
+use output::print_table;
+use output::OutputFormat;
+use top_terms::for_each_merged_term_info;
+use top_terms::TantivyValue;
+use State;
+
+#[derive(Serialize)]
+pub struct DocAddress {
+    segment: String,
+    doc: DocId,
+}
+
+#[derive(Serialize)]
+pub struct DuplicateTerm {
+    term: String,
+    docs: Vec<DocAddress>,
+}
+
+#[derive(Serialize)]
+pub struct DuplicatesData {
+    field: String,
+    duplicates: Vec<DuplicateTerm>,
+    truncated: bool,
+}
+
+/// Finds terms of `field` that more than one live doc shares, across all segments.
+pub fn duplicates(index: &Index, field_name: &str, limit: usize) -> Result<DuplicatesData, Error> {
+    let schema = index.schema();
+    let field = schema.get_field(field_name).ok_or(err_msg("Sorry, that field does not exist!"))?;
+    let field_type = schema.get_field_entry(field).field_type();
+    if field_type.get_index_record_option().is_none() {
+        return Err(err_msg("Only indexed fields can be checked for duplicates"));
+    }
+    let value_type = field_type.value_type();
+
+    let searcher = index.searcher();
+    let segment_readers = searcher.segment_readers();
+    let inverted_indexes = segment_readers.iter().map(|x| x.inverted_index(field)).collect::<Vec<_>>();
+
+    let mut duplicates = Vec::new();
+    let mut truncated = false;
+    for_each_merged_term_info(&*searcher, field, |key, term_infos| {
+        if truncated {
+            return;
+        }
+        // Deletes can only lower the count, so a single posting can't be a duplicate.
+        let doc_freq: u32 = term_infos.iter().map(|&(_, ref term_info)| term_info.doc_freq).sum();
+        if doc_freq < 2 {
+            return;
+        }
+
+        let mut docs = Vec::new();
+        for &(segment_ord, ref term_info) in term_infos {
+            let segment = &segment_readers[segment_ord];
+            let mut postings = inverted_indexes[segment_ord].read_postings_from_terminfo(term_info, IndexRecordOption::Basic);
+            while postings.advance() {
+                if !segment.is_deleted(postings.doc()) {
+                    docs.push(DocAddress {
+                        segment: segment.segment_id().uuid_string(),
+                        doc: postings.doc(),
+                    });
+                }
+            }
+        }
+
+        if docs.len() > 1 {
+            if duplicates.len() >= limit {
+                truncated = true;
+                return;
+            }
+            duplicates.push(DuplicateTerm {
+                term: format!("{}", TantivyValue::from_term(key, value_type)),
+                docs,
+            });
+        }
+    });
+
+    Ok(DuplicatesData {
+        field: field_name.to_string(),
+        duplicates,
+        truncated,
+    })
+}
+
+#[derive(Deserialize)]
+pub struct DuplicatesQuery {
+    field: Option<String>,
+    limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct DuplicatesPage {
+    fields: Vec<String>,
+    result: Option<DuplicatesData>,
+}
+
+pub(crate) fn handle_duplicates(req: (HttpRequest<State>, Query<DuplicatesQuery>)) -> Result<HttpResponse, Error> {
+    let (req, params) = req;
+    let state = req.state();
+    let mut fields = state.index.schema()
+        .fields()
+        .iter()
+        .filter(|x| x.field_type().get_index_record_option().is_some())
+        .map(|x| x.name().to_string())
+        .collect::<Vec<_>>();
+    fields.sort();
+
+    let result = match params.field {
+        Some(ref field) => Some(duplicates(&state.index, field, params.limit.unwrap_or(1000))?),
+        None => None,
+    };
+
+    Ok(state.render_template("duplicates", &DuplicatesPage { fields, result })?)
+}
+
+pub fn run_duplicates(index: &Index, field_name: &str, limit: usize, format: OutputFormat) -> Result<(), Error> {
+    let data = duplicates(index, field_name, limit)?;
+    match format {
+        OutputFormat::Json => print_json(&data)?,
+        OutputFormat::Table => {
+            let rows = data.duplicates.iter()
+                .map(|x| vec![
+                    x.term.clone(),
+                    x.docs.len().to_string(),
+                    x.docs.iter().map(|doc| format!("{}/{}", doc.segment, doc.doc)).collect::<Vec<_>>().join(" "),
+                ])
+                .collect::<Vec<_>>();
+            print_table(&["term", "live docs", "docs"], &rows);
+            if data.truncated {
+                println!();
+                println!("Stopped after {} duplicated terms.", limit);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tantivy::Document;
+    use tantivy::Term;
+    use tantivy::merge_policy::NoMergePolicy;
+    use tantivy::schema::SchemaBuilder;
+    use tantivy::schema::STRING;
+
+    #[test]
+    fn test_duplicates_skip_deleted_docs() {
+        let mut schema_builder = SchemaBuilder::default();
+        let id = schema_builder.add_text_field("id", STRING);
+        let key = schema_builder.add_text_field("key", STRING);
+        let index = Index::create_in_ram(schema_builder.build());
+        let mut writer = index.writer_with_num_threads(1, 40_000_000).unwrap();
+        writer.set_merge_policy(Box::new(NoMergePolicy));
+        for segment in vec![vec![("a", "1"), ("b", "2"), ("c", "3")], vec![("a", "4"), ("c", "5"), ("d", "6")]] {
+            for (id_value, key_value) in segment {
+                let mut document = Document::default();
+                document.add_text(id, id_value);
+                document.add_text(key, key_value);
+                writer.add_document(document);
+            }
+            writer.commit().unwrap();
+        }
+        writer.delete_term(Term::from_field_text(key, "5"));
+        writer.commit().unwrap();
+        index.load_searchers().unwrap();
+
+        let data = duplicates(&index, "id", 10).unwrap();
+        let terms = data.duplicates.iter().map(|x| (&x.term[..], x.docs.len())).collect::<Vec<_>>();
+        assert_eq!(terms, vec![("a", 2)]);
+        assert!(!data.truncated);
+
+        let data = duplicates(&index, "id", 0).unwrap();
+        assert!(data.duplicates.is_empty());
+        assert!(data.truncated);
+    }
+}
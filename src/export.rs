@@ -0,0 +1,476 @@
+use std::collections::VecDeque;
+use std::io;
+use std::io::Write;
+use std::mem;
+use actix_web;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use actix_web::Query;
+use actix_web::http;
+use bytes::Bytes;
+use failure::err_msg;
+use failure::Error;
+use futures::stream;
+use serde_json;
+use tantivy::DocId;
+use tantivy::Index;
+use tantivy::Score;
+use tantivy::SegmentReader;
+use tantivy::collector::Collector;
+use tantivy::query::QueryParser;
+use tantivy::query::Weight;
+use tantivy::schema::Field;
+use tantivy::schema::Schema;
+use tantivy::schema::Type;
+
+use reconstruct::reconstruct_segment;
+use segment_collect_first_k;
+use top_terms::TantivyValue;
+use uninverted::uninvert;
+use State;
+use TantivyViewerError;
+
+/// Docs reconstructed at a time, and written per chunk of a response body.
+const BATCH_SIZE: usize = 1000;
+/// An indexed field is uninverted for a whole segment once at least one in this many of
+/// the segment's docs are wanted.
+const UNINVERT_MIN_SHARE: usize = 4;
+
+#[derive(Clone, Copy)]
+pub enum ExportFormat {
+    Ndjson,
+    Csv,
+}
+
+impl ExportFormat {
+    pub fn parse(format: Option<&str>) -> Result<ExportFormat, Error> {
+        match format {
+            None | Some("ndjson") => Ok(ExportFormat::Ndjson),
+            Some("csv") => Ok(ExportFormat::Csv),
+            Some(other) => Err(err_msg(format!("Unknown export format '{}'. Expected ndjson or csv", other))),
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match *self {
+            ExportFormat::Ndjson => "application/x-ndjson",
+            ExportFormat::Csv => "text/csv",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            ExportFormat::Ndjson => "ndjson",
+            ExportFormat::Csv => "csv",
+        }
+    }
+}
+
+/// Quotes a CSV field if it contains anything that would break the row apart.
+pub(crate) fn csv_field(value: &str) -> String {
+    if value.contains(|c: char| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+pub(crate) fn value_to_json(value: &TantivyValue) -> serde_json::Value {
+    match *value {
+        TantivyValue::I64(value) => value.into(),
+        TantivyValue::U64(value) => value.into(),
+        TantivyValue::Text(ref value) => value.clone().into(),
+        TantivyValue::Bytes(ref value) => value.clone().into(),
+    }
+}
+
+/// Reconstructed values with the gaps between positions dropped.
+pub(crate) fn present_values(values: Vec<Option<TantivyValue>>) -> Vec<TantivyValue> {
+    values.into_iter().filter_map(|x| x).collect()
+}
+
+#[derive(Serialize)]
+struct NdjsonRow<'a> {
+    segment: &'a str,
+    doc: DocId,
+    values: Vec<serde_json::Value>,
+}
+
+fn export_row(format: ExportFormat, segment: &str, doc: DocId, values: Vec<TantivyValue>) -> Result<String, Error> {
+    Ok(match format {
+        ExportFormat::Ndjson => {
+            let row = NdjsonRow {
+                segment,
+                doc,
+                values: values.iter().map(value_to_json).collect(),
+            };
+            let json = serde_json::to_string(&row).map_err(|_e| TantivyViewerError::JsonSerializationError)?;
+            format!("{}\n", json)
+        }
+        ExportFormat::Csv => {
+            let joined = values.iter().map(|x| format!("{}", x)).collect::<Vec<_>>().join(" ");
+            format!("{},{},{}\n", segment, doc, csv_field(&joined))
+        }
+    })
+}
+
+pub(crate) fn reconstructable_field(index: &Index, field_name: &str) -> Result<Field, Error> {
+    let schema = index.schema();
+    let field = schema.get_field(field_name).ok_or(err_msg("Sorry, that field does not exist!"))?;
+    match schema.get_field_entry(field).field_type().value_type() {
+        Type::HierarchicalFacet => Err(err_msg("Facet fields can't be reconstructed")),
+        _ => Ok(field),
+    }
+}
+
+/// Reconstructs one field for docs of a segment, handed out in batches.
+///
+/// When a large share of the segment is wanted, the field is uninverted once for the whole
+/// segment, since reconstructing it a batch at a time would walk the term dictionary again
+/// for every batch. Otherwise each batch skips through the postings for just its docs, so
+/// memory stays proportional to the batch rather than the segment.
+pub(crate) struct SegmentFieldValues {
+    field: Field,
+    uninverted: Option<Vec<Vec<Option<TantivyValue>>>>,
+}
+
+impl SegmentFieldValues {
+    /// `num_docs` is how many docs of the segment will be taken in total.
+    pub(crate) fn open(schema: &Schema, segment: &SegmentReader, field: Field, num_docs: usize) -> Result<SegmentFieldValues, Error> {
+        let field_type = schema.get_field_entry(field).field_type();
+        let worth_uninverting = num_docs * UNINVERT_MIN_SHARE >= segment.max_doc() as usize;
+        let uninverted = match field_type.get_index_record_option() {
+            Some(options) if worth_uninverting => uninvert(segment, field, field_type.value_type(), options, usize::MAX).map(|x| x.docs),
+            _ => None,
+        };
+        Ok(SegmentFieldValues { field, uninverted })
+    }
+
+    /// Values of each of `docs`, in order. `docs` must be sorted, and each doc can only be
+    /// taken once.
+    pub(crate) fn take(&mut self, schema: &Schema, segment: &SegmentReader, docs: &[DocId]) -> Result<Vec<Vec<Option<TantivyValue>>>, Error> {
+        match self.uninverted {
+            Some(ref mut uninverted) => Ok(docs.iter()
+                .map(|&doc| mem::replace(&mut uninverted[doc as usize], Vec::new()))
+                .collect()),
+            None => Ok(reconstruct_segment(schema, segment, self.field, docs)?
+                .into_iter()
+                .map(|(_doc, values)| values)
+                .collect()),
+        }
+    }
+}
+
+/// Gathers the hits of a single segment.
+struct SegmentHits {
+    docs: Vec<(DocId, Score)>,
+}
+
+impl Collector for SegmentHits {
+    fn set_segment(&mut self, _segment_local_id: u32, _segment: &SegmentReader) -> Result<(), ::tantivy::Error> {
+        Ok(())
+    }
+
+    fn collect(&mut self, doc: DocId, score: Score) {
+        self.docs.push((doc, score));
+    }
+
+    fn requires_scoring(&self) -> bool {
+        false
+    }
+}
+
+/// Live docs of a segment with their scores, optionally only those matching `weight`.
+///
+/// Matches go through the same `segment_collect_first_k` as the search page, just without
+/// a limit.
+fn segment_docs(weight: Option<&Weight>, segment: &SegmentReader) -> Result<Vec<(DocId, Score)>, Error> {
+    match weight {
+        Some(weight) => {
+            let mut scorer = weight.scorer(segment).map_err(TantivyViewerError::TantivyError)?;
+            let mut hits = SegmentHits { docs: Vec::new() };
+            segment_collect_first_k(&mut scorer, &mut hits, segment.delete_bitset(), usize::MAX);
+            Ok(hits.docs)
+        }
+        None => Ok((0..segment.max_doc()).filter(|&doc| !segment.is_deleted(doc)).map(|doc| (doc, 1.0)).collect()),
+    }
+}
+
+/// Docs of one segment along with their reconstructed fields.
+pub(crate) struct DocBatch {
+    pub(crate) segment: SegmentReader,
+    pub(crate) docs: Vec<(DocId, Score)>,
+    /// Values of each requested field, then of each doc.
+    pub(crate) values: Vec<Vec<Vec<Option<TantivyValue>>>>,
+}
+
+struct SegmentCursor {
+    segment: SegmentReader,
+    docs: Vec<(DocId, Score)>,
+    offset: usize,
+    values: Vec<SegmentFieldValues>,
+}
+
+/// Walks every live doc, or every live doc matching a query, a segment at a time and in
+/// batches within it, reconstructing the requested fields along the way.
+///
+/// The segment readers all come from the searcher current when the walk starts, so a merge
+/// happening meanwhile can't make a segment disappear halfway through. Only the current
+/// segment's doc ids, and values of fields worth uninverting, are held in memory at once.
+pub(crate) struct DocBatches {
+    schema: Schema,
+    weight: Option<Box<Weight>>,
+    fields: Vec<Field>,
+    segments: VecDeque<SegmentReader>,
+    cursor: Option<SegmentCursor>,
+}
+
+impl DocBatches {
+    pub(crate) fn new(index: &Index, query: Option<&::tantivy::query::Query>, scoring: bool, field_names: &[String]) -> Result<DocBatches, Error> {
+        let mut fields = Vec::new();
+        for field_name in field_names {
+            fields.push(reconstructable_field(index, field_name)?);
+        }
+        let searcher = index.searcher();
+        let weight = match query {
+            Some(query) => Some(query.weight(&*searcher, scoring).map_err(TantivyViewerError::TantivyError)?),
+            None => None,
+        };
+        Ok(DocBatches {
+            schema: index.schema(),
+            weight,
+            fields,
+            segments: searcher.segment_readers().iter().cloned().collect(),
+            cursor: None,
+        })
+    }
+
+    pub(crate) fn next_batch(&mut self) -> Result<Option<DocBatch>, Error> {
+        loop {
+            if let Some(mut cursor) = self.cursor.take() {
+                if cursor.offset < cursor.docs.len() {
+                    let end = (cursor.offset + BATCH_SIZE).min(cursor.docs.len());
+                    let docs = cursor.docs[cursor.offset..end].to_vec();
+                    cursor.offset = end;
+
+                    let doc_ids = docs.iter().map(|&(doc, _score)| doc).collect::<Vec<_>>();
+                    let mut values = Vec::new();
+                    for field_values in cursor.values.iter_mut() {
+                        values.push(field_values.take(&self.schema, &cursor.segment, &doc_ids)?);
+                    }
+                    let batch = DocBatch {
+                        segment: cursor.segment.clone(),
+                        docs,
+                        values,
+                    };
+                    self.cursor = Some(cursor);
+                    return Ok(Some(batch));
+                }
+            }
+
+            let segment = match self.segments.pop_front() {
+                Some(segment) => segment,
+                None => return Ok(None),
+            };
+            let docs = segment_docs(self.weight.as_ref().map(|x| &**x), &segment)?;
+            if docs.is_empty() {
+                continue;
+            }
+            let mut values = Vec::new();
+            for &field in self.fields.iter() {
+                values.push(SegmentFieldValues::open(&self.schema, &segment, field, docs.len())?);
+            }
+            self.cursor = Some(SegmentCursor {
+                segment,
+                docs,
+                offset: 0,
+                values,
+            });
+        }
+    }
+}
+
+/// Writes one field of every live doc, or of every live doc matching a query, a batch at a time.
+pub struct FieldExport {
+    batches: DocBatches,
+    field_name: String,
+    format: ExportFormat,
+    header_written: bool,
+}
+
+impl FieldExport {
+    pub fn new(index: &Index, field_name: &str, query: Option<&::tantivy::query::Query>, format: ExportFormat) -> Result<FieldExport, Error> {
+        Ok(FieldExport {
+            batches: DocBatches::new(index, query, false, &[field_name.to_string()])?,
+            field_name: field_name.to_string(),
+            format,
+            header_written: false,
+        })
+    }
+
+    fn write_batch(&self, mut batch: DocBatch) -> Result<Bytes, Error> {
+        let segment = batch.segment.segment_id().uuid_string();
+        let mut output = String::new();
+        for (&(doc, _score), values) in batch.docs.iter().zip(batch.values.remove(0)) {
+            output.push_str(&export_row(self.format, &segment, doc, present_values(values))?);
+        }
+        Ok(output.into())
+    }
+}
+
+impl Iterator for FieldExport {
+    type Item = Result<Bytes, Error>;
+
+    fn next(&mut self) -> Option<Result<Bytes, Error>> {
+        if !self.header_written {
+            self.header_written = true;
+            if let ExportFormat::Csv = self.format {
+                return Some(Ok(format!("segment,doc,{}\n", csv_field(&self.field_name)).into()));
+            }
+        }
+        match self.batches.next_batch() {
+            Ok(Some(batch)) => Some(self.write_batch(batch)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// Turns a field name into something safe inside a quoted `Content-Disposition` filename,
+/// replacing everything but ASCII letters, digits, `-`, `_` and `.`.
+pub(crate) fn download_filename(name: &str) -> String {
+    let filename = name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' { c } else { '_' })
+        .collect::<String>();
+    if filename.is_empty() {
+        "export".to_string()
+    } else {
+        filename
+    }
+}
+
+/// Streams `export` as the body of a download named after `filename`.
+pub(crate) fn streaming_download<I>(export: I, format: ExportFormat, filename: &str) -> HttpResponse
+    where I: Iterator<Item=Result<Bytes, Error>> + 'static {
+    HttpResponse::Ok()
+        .content_type(format.content_type())
+        .header(http::header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}.{}\"", download_filename(filename), format.extension()))
+        .streaming(stream::iter_result(export.map(|x| x.map_err(actix_web::Error::from))))
+}
+
+/// Writes `export` to stdout, for the subcommands that mirror the download links.
+pub(crate) fn write_export<I>(export: I) -> Result<(), Error>
+    where I: Iterator<Item=Result<Bytes, Error>> {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    for chunk in export {
+        stdout.write_all(&chunk?)?;
+    }
+    stdout.flush()?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    field: String,
+    query: Option<String>,
+    format: Option<String>,
+}
+
+pub(crate) fn handle_export(req: (HttpRequest<State>, Query<ExportQuery>)) -> Result<HttpResponse, Error> {
+    let (req, params) = req;
+    let state = req.state();
+    let format = ExportFormat::parse(params.format.as_ref().map(|x| x.as_str()))?;
+    let query = match params.query {
+        Some(ref query) if !query.trim().is_empty() => {
+            let query_parser = QueryParser::for_index(&state.index, vec![]);
+            Some(query_parser.parse_query(query).map_err(TantivyViewerError::QueryParserError)?)
+        }
+        _ => None,
+    };
+    let export = FieldExport::new(&state.index, &params.field, query.as_ref().map(|x| &**x), format)?;
+    Ok(streaming_download(export, format, &params.field))
+}
+
+pub fn run_export(index: &Index, field_name: &str, query: Option<&str>, format: ExportFormat) -> Result<(), Error> {
+    let query = match query {
+        Some(query) => {
+            let query_parser = QueryParser::for_index(index, vec![]);
+            Some(query_parser.parse_query(query).map_err(TantivyViewerError::QueryParserError)?)
+        }
+        None => None,
+    };
+    write_export(FieldExport::new(index, field_name, query.as_ref().map(|x| &**x), format)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tantivy::Document;
+    use tantivy::schema::SchemaBuilder;
+    use tantivy::schema::STRING;
+    use tantivy::schema::TEXT;
+
+    fn test_index(num_docs: usize) -> Index {
+        let mut schema_builder = SchemaBuilder::default();
+        let id = schema_builder.add_text_field("id", STRING);
+        let title = schema_builder.add_text_field("title", TEXT);
+        let index = Index::create_in_ram(schema_builder.build());
+        let mut writer = index.writer_with_num_threads(1, 40_000_000).unwrap();
+        for i in 0..num_docs {
+            let mut document = Document::default();
+            document.add_text(id, &format!("id{}", i));
+            document.add_text(title, if i % 2 == 0 { "even doc" } else { "odd doc" });
+            writer.add_document(document);
+        }
+        writer.commit().unwrap();
+        index.load_searchers().unwrap();
+        index
+    }
+
+    fn export_text(export: FieldExport) -> String {
+        let chunks = export.collect::<Result<Vec<_>, _>>().unwrap();
+        chunks.iter().map(|x| String::from_utf8(x.to_vec()).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_csv_field() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn test_download_filename() {
+        assert_eq!(download_filename("title"), "title");
+        assert_eq!(download_filename("my field.v2"), "my_field.v2");
+        assert_eq!(download_filename("a\"; b=c\r\n"), "a___b_c__");
+        assert_eq!(download_filename(""), "export");
+    }
+
+    #[test]
+    fn test_export_row() {
+        let values = vec![TantivyValue::Text("a".to_string()), TantivyValue::Text("b,c".to_string())];
+        assert_eq!(export_row(ExportFormat::Csv, "seg", 3, values.clone()).unwrap(), "seg,3,\"a b,c\"\n");
+        assert_eq!(export_row(ExportFormat::Ndjson, "seg", 3, values).unwrap(), "{\"segment\":\"seg\",\"doc\":3,\"values\":[\"a\",\"b,c\"]}\n");
+    }
+
+    #[test]
+    fn test_field_export_with_query() {
+        let index = test_index(4);
+        let segment = index.searchable_segment_ids().unwrap()[0].uuid_string();
+        let query = QueryParser::for_index(&index, vec![]).parse_query("title:odd").unwrap();
+        let export = FieldExport::new(&index, "id", Some(&*query), ExportFormat::Csv).unwrap();
+        assert_eq!(export_text(export), format!("segment,doc,id\n{0},1,id1\n{0},3,id3\n", segment));
+    }
+
+    #[test]
+    fn test_field_export_spans_batches() {
+        let index = test_index(BATCH_SIZE * 2 + 10);
+        let export = FieldExport::new(&index, "id", None, ExportFormat::Ndjson).unwrap();
+        let text = export_text(export);
+        assert_eq!(text.lines().count(), BATCH_SIZE * 2 + 10);
+        assert!(text.lines().last().unwrap().contains(&format!("\"id{}\"", BATCH_SIZE * 2 + 9)));
+    }
+}
@@ -0,0 +1,169 @@
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use actix_web::Form;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use actix_web::Query;
+use failure::err_msg;
+use failure::Error;
+use tantivy::Index;
+use tantivy::query::QueryParser;
+
+use dump::for_each_document;
+use dump::WRITER_HEAP_BYTES;
+use State;
+use TantivyViewerError;
+
+/// Writes the live docs matching `query` into a new index at `output_dir`, with the same schema.
+///
+/// Stored fields come from the doc store and everything else is reconstructed, as for `dump`.
+pub fn extract(index: &Index, query: &::tantivy::query::Query, output_dir: &Path) -> Result<u64, Error> {
+    if output_dir.exists() && fs::read_dir(output_dir)?.next().is_some() {
+        return Err(err_msg(format!("{} is not empty", output_dir.display())));
+    }
+    fs::create_dir_all(output_dir)?;
+    let extracted = Index::create_in_dir(output_dir, index.schema()).map_err(TantivyViewerError::TantivyError)?;
+    let mut writer = extracted.writer(WRITER_HEAP_BYTES).map_err(TantivyViewerError::TantivyError)?;
+
+    let mut num_docs = 0;
+    for_each_document(index, Some(query), |_segment, _doc, document| {
+        writer.add_document(document);
+        num_docs += 1;
+        Ok(())
+    })?;
+    writer.commit().map_err(TantivyViewerError::TantivyError)?;
+    writer.wait_merging_threads().map_err(TantivyViewerError::TantivyError)?;
+    Ok(num_docs)
+}
+
+fn parse_and_extract(index: &Index, raw_query: &str, output_dir: &Path) -> Result<u64, Error> {
+    let query_parser = QueryParser::for_index(index, vec![]);
+    let query = query_parser.parse_query(raw_query).map_err(TantivyViewerError::QueryParserError)?;
+    extract(index, &*query, output_dir)
+}
+
+pub fn run_extract(index: &Index, raw_query: &str, output_dir: &Path) -> Result<(), Error> {
+    let num_docs = parse_and_extract(index, raw_query, output_dir)?;
+    println!("Extracted {} docs into {}", num_docs, output_dir.display());
+    Ok(())
+}
+
+/// Where the page writes an index called `name`: directly under `extract_dir`, as `name`
+/// has to be a single plain path component.
+pub(crate) fn extract_output(extract_dir: &Path, name: &str) -> Result<PathBuf, Error> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+    if !valid {
+        return Err(err_msg("The name may only hold ASCII letters, digits, '-', '_' and '.', and may not start with '.'"));
+    }
+    Ok(extract_dir.join(name))
+}
+
+#[derive(Serialize)]
+struct ExtractResult {
+    output: String,
+    num_docs: u64,
+}
+
+#[derive(Serialize)]
+struct ExtractPage {
+    enabled: bool,
+    extract_dir: Option<String>,
+    query: String,
+    name: String,
+    /// Set once an extraction has run, including when nothing matched.
+    result: Option<ExtractResult>,
+}
+
+impl ExtractPage {
+    fn new(state: &State, query: String, name: String) -> ExtractPage {
+        ExtractPage {
+            enabled: state.extract_dir.is_some(),
+            extract_dir: state.extract_dir.as_ref().map(|x| x.display().to_string()),
+            query,
+            name,
+            result: None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ExtractQuery {
+    query: Option<String>,
+}
+
+pub(crate) fn handle_extract_page(req: (HttpRequest<State>, Query<ExtractQuery>)) -> Result<HttpResponse, Error> {
+    let (req, params) = req;
+    let state = req.state();
+    let page = ExtractPage::new(state, params.query.clone().unwrap_or_default(), String::new());
+    Ok(state.render_template("extract", &page)?)
+}
+
+#[derive(Deserialize)]
+pub struct ExtractForm {
+    query: String,
+    name: String,
+}
+
+/// Only answers POST, since it writes to disk, and only ever creates a new index directly
+/// under `--extract-dir`. Without that option the page stays read-only.
+pub(crate) fn handle_extract(req: (HttpRequest<State>, Form<ExtractForm>)) -> Result<HttpResponse, Error> {
+    let (req, form) = req;
+    let state = req.state();
+    let extract_dir = state.extract_dir.as_ref()
+        .ok_or(err_msg("Extraction from the web UI is disabled. Start the viewer with --extract-dir to enable it"))?;
+    let output = extract_output(extract_dir, &form.name)?;
+    let num_docs = parse_and_extract(&state.index, &form.query, &output)?;
+    let mut page = ExtractPage::new(state, form.query.clone(), form.name.clone());
+    page.result = Some(ExtractResult {
+        output: output.display().to_string(),
+        num_docs,
+    });
+    Ok(state.render_template("extract", &page)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+    use tantivy::Document;
+    use tantivy::schema::SchemaBuilder;
+    use tantivy::schema::STORED;
+    use tantivy::schema::TEXT;
+
+    #[test]
+    fn test_extract_output_stays_under_the_extract_dir() {
+        let extract_dir = Path::new("/srv/extracts");
+        assert_eq!(extract_output(extract_dir, "crashes-2018.v1").unwrap(), extract_dir.join("crashes-2018.v1"));
+        for name in &["", ".", "..", ".hidden", "../etc", "a/b", "/tmp/x", "a\\b", "caf\u{e9}"] {
+            assert!(extract_output(extract_dir, name).is_err(), "'{}' should be refused", name);
+        }
+    }
+
+    #[test]
+    fn test_extract() {
+        let mut schema_builder = SchemaBuilder::default();
+        let title = schema_builder.add_text_field("title", TEXT | STORED);
+        let index = Index::create_in_ram(schema_builder.build());
+        let mut writer = index.writer_with_num_threads(1, 40_000_000).unwrap();
+        for text in &["red apple", "green pear", "red cherry"] {
+            let mut document = Document::default();
+            document.add_text(title, text);
+            writer.add_document(document);
+        }
+        writer.commit().unwrap();
+        index.load_searchers().unwrap();
+
+        let output_dir = env::temp_dir().join(format!("tantivy-viewer-extract-{}", process::id()));
+        let _ = fs::remove_dir_all(&output_dir);
+        assert_eq!(parse_and_extract(&index, "title:red", &output_dir).unwrap(), 2);
+        let extracted = Index::open_in_dir(&output_dir).unwrap();
+        assert_eq!(extracted.searcher().num_docs(), 2);
+        // An index is never written over an existing one.
+        assert!(parse_and_extract(&index, "title:pear", &output_dir).is_err());
+        fs::remove_dir_all(&output_dir).unwrap();
+    }
+}
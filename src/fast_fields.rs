@@ -0,0 +1,341 @@
+use std::fmt::Display;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use actix_web::Query;
+use failure::err_msg;
+use failure::Error;
+use tantivy::Index;
+use tantivy::fastfield::FastValue;
+use tantivy::schema::Cardinality;
+use tantivy::schema::Field;
+use tantivy::schema::FieldType;
+
+use output::print_json;
+use output::print_table;
+use output::OutputFormat;
+use State;
+
+const NUM_BUCKETS: usize = 20;
+const PERCENTILES: &[u32] = &[1, 5, 25, 50, 75, 95, 99];
+
+#[derive(Serialize)]
+pub struct Percentile {
+    percentile: u32,
+    value: String,
+}
+
+#[derive(Serialize)]
+pub struct Bucket {
+    from: String,
+    to: String,
+    count: u64,
+    percent: String,
+}
+
+#[derive(Serialize)]
+pub struct FastFieldStats {
+    field: String,
+    cardinality: String,
+    live_docs: u64,
+    docs_without_values: u64,
+    num_values: u64,
+    min: String,
+    max: String,
+    mean: String,
+    /// Estimated with HyperLogLog.
+    distinct_values: u64,
+    /// Percentiles come from a sample once there are more values than `SAMPLE_SIZE`.
+    percentiles_exact: bool,
+    percentiles: Vec<Percentile>,
+    histogram: Vec<Bucket>,
+    never_populated: bool,
+}
+
+/// Values kept for percentiles. Fields with at most this many values get exact percentiles.
+const SAMPLE_SIZE: usize = 10_000;
+/// Bits of the hash used to pick a HyperLogLog register, giving about 1.6% error.
+const HLL_PRECISION: u32 = 12;
+
+/// The splitmix64 finalizer, to spread fast field values over the whole hash space.
+fn mix64(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+/// Estimates the number of distinct values in constant memory.
+struct HyperLogLog {
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    fn new() -> HyperLogLog {
+        HyperLogLog { registers: vec![0; 1 << HLL_PRECISION] }
+    }
+
+    fn insert(&mut self, value: u64) {
+        let hash = mix64(value);
+        let register = (hash >> (64 - HLL_PRECISION)) as usize;
+        let rank = ((hash << HLL_PRECISION) | (1 << (HLL_PRECISION - 1))).leading_zeros() as u8 + 1;
+        if rank > self.registers[register] {
+            self.registers[register] = rank;
+        }
+    }
+
+    fn estimate(&self) -> u64 {
+        let m = self.registers.len() as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum = self.registers.iter().map(|&x| 2f64.powi(-(x as i32))).sum::<f64>();
+        let estimate = alpha * m * m / sum;
+        let zeros = self.registers.iter().filter(|&&x| x == 0).count();
+        // Small cardinalities are better estimated by counting empty registers.
+        if estimate <= 2.5 * m && zeros > 0 {
+            (m * (m / zeros as f64).ln()).round() as u64
+        } else {
+            estimate.round() as u64
+        }
+    }
+}
+
+/// Calls `f` with every value of a fast field for all live docs in the index, and returns
+/// the number of live docs and of live docs without any value.
+fn for_each_value<T: FastValue, F: FnMut(T)>(index: &Index, field: Field, cardinality: Cardinality, mut f: F) -> Result<(u64, u64), Error> {
+    let searcher = index.searcher();
+    let mut live_docs = 0;
+    let mut docs_without_values = 0;
+    let mut doc_values = Vec::new();
+
+    for segment in searcher.segment_readers() {
+        match cardinality {
+            Cardinality::SingleValue => {
+                let reader = segment.fast_field_reader::<T>(field)?;
+                for doc in 0..segment.max_doc() {
+                    if !segment.is_deleted(doc) {
+                        live_docs += 1;
+                        f(reader.get(doc));
+                    }
+                }
+            }
+            Cardinality::MultiValues => {
+                let reader = segment.multi_fast_field_reader::<T>(field)?;
+                for doc in 0..segment.max_doc() {
+                    if !segment.is_deleted(doc) {
+                        live_docs += 1;
+                        reader.get_vals(doc, &mut doc_values);
+                        if doc_values.is_empty() {
+                            docs_without_values += 1;
+                        }
+                        for value in doc_values.drain(..) {
+                            f(value);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    Ok((live_docs, docs_without_values))
+}
+
+/// Computes the statistics in two passes over the fast field: one for the counts, bounds,
+/// distinct value estimate and a uniform sample for percentiles, and one to fill in the
+/// histogram once its bounds are known. Memory stays bounded whatever the index size.
+fn numeric_stats<T: FastValue + Ord + Copy + Display>(index: &Index, field_name: &str, field: Field, cardinality: Cardinality, to_f64: fn(T) -> f64) -> Result<FastFieldStats, Error> {
+    let cardinality_name = format!("{:?}", cardinality);
+
+    let mut num_values = 0u64;
+    let mut sum = 0.0;
+    let mut first: Option<T> = None;
+    let mut last: Option<T> = None;
+    let mut distinct = HyperLogLog::new();
+    let mut sample = Vec::new();
+    let (live_docs, docs_without_values) = for_each_value::<T, _>(index, field, cardinality, |value| {
+        num_values += 1;
+        sum += to_f64(value);
+        first = Some(first.map(|x| x.min(value)).unwrap_or(value));
+        last = Some(last.map(|x| x.max(value)).unwrap_or(value));
+        distinct.insert(value.to_u64());
+        // Reservoir sampling, with a fixed seed so reloading the page gives the same figures.
+        if sample.len() < SAMPLE_SIZE {
+            sample.push(value);
+        } else {
+            let slot = (mix64(num_values) % num_values) as usize;
+            if slot < SAMPLE_SIZE {
+                sample[slot] = value;
+            }
+        }
+    })?;
+    sample.sort();
+
+    let format_opt = |x: Option<T>| x.map(|x| format!("{}", x)).unwrap_or_else(|| "-".to_string());
+
+    let mean = if num_values == 0 {
+        "-".to_string()
+    } else {
+        format!("{:.2}", sum / num_values as f64)
+    };
+
+    let percentiles = PERCENTILES.iter()
+        .map(|&percentile| Percentile {
+            percentile,
+            value: format_opt(if sample.is_empty() {
+                None
+            } else {
+                let rank = (percentile as usize * sample.len() + 99) / 100;
+                Some(sample[rank.max(1) - 1])
+            }),
+        })
+        .collect();
+
+    let mut histogram = Vec::new();
+    if let (Some(min), Some(max)) = (first, last) {
+        let (min, max) = (to_f64(min), to_f64(max));
+        let width = (max - min) / NUM_BUCKETS as f64;
+        let mut counts = vec![0u64; if width > 0.0 { NUM_BUCKETS } else { 1 }];
+        let num_buckets = counts.len();
+        for_each_value::<T, _>(index, field, cardinality, |value| {
+            let bucket = if width > 0.0 { ((to_f64(value) - min) / width) as usize } else { 0 };
+            counts[bucket.min(num_buckets - 1)] += 1;
+        })?;
+        let max_count = counts.iter().cloned().max().unwrap_or(0);
+        for (idx, &count) in counts.iter().enumerate() {
+            histogram.push(Bucket {
+                from: format!("{:.0}", min + width * idx as f64),
+                to: format!("{:.0}", if width > 0.0 { min + width * (idx + 1) as f64 } else { max }),
+                count,
+                percent: format!("{:.1}", count as f64 * 100.0 / max_count as f64),
+            });
+        }
+    }
+
+    Ok(FastFieldStats {
+        field: field_name.to_string(),
+        cardinality: cardinality_name,
+        live_docs,
+        docs_without_values,
+        num_values,
+        min: format_opt(first),
+        max: format_opt(last),
+        mean,
+        distinct_values: distinct.estimate().min(num_values),
+        percentiles_exact: num_values <= SAMPLE_SIZE as u64,
+        percentiles,
+        histogram,
+        never_populated: num_values > 0 && first.map(to_f64) == Some(0.0) && last.map(to_f64) == Some(0.0),
+    })
+}
+
+/// Distribution of a U64 or I64 fast field over the live docs of the index.
+pub fn fast_field_stats(index: &Index, field_name: &str) -> Result<FastFieldStats, Error> {
+    let schema = index.schema();
+    let field = schema.get_field(field_name).ok_or(err_msg("Sorry, that field does not exist!"))?;
+    match *schema.get_field_entry(field).field_type() {
+        FieldType::U64(ref opts) => {
+            let cardinality = opts.get_fastfield_cardinality().ok_or(err_msg("Field is not a fast field"))?;
+            numeric_stats::<u64>(index, field_name, field, cardinality, |x| x as f64)
+        }
+        FieldType::I64(ref opts) => {
+            let cardinality = opts.get_fastfield_cardinality().ok_or(err_msg("Field is not a fast field"))?;
+            numeric_stats::<i64>(index, field_name, field, cardinality, |x| x as f64)
+        }
+        _ => Err(err_msg("Only U64 and I64 fast fields have numeric statistics")),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct FastFieldStatsQuery {
+    field: String,
+}
+
+pub(crate) fn handle_fast_field_stats(req: (HttpRequest<State>, Query<FastFieldStatsQuery>)) -> Result<HttpResponse, Error> {
+    let (req, params) = req;
+    let state = req.state();
+    let data = fast_field_stats(&state.index, &params.field)?;
+    Ok(state.render_template("fast_field_stats", &data)?)
+}
+
+pub fn run_fast_field_stats(index: &Index, field_name: &str, format: OutputFormat) -> Result<(), Error> {
+    let data = fast_field_stats(index, field_name)?;
+    match format {
+        OutputFormat::Json => print_json(&data)?,
+        OutputFormat::Table => {
+            let summary = vec![
+                vec!["cardinality".to_string(), data.cardinality.clone()],
+                vec!["live docs".to_string(), data.live_docs.to_string()],
+                vec!["docs without values".to_string(), data.docs_without_values.to_string()],
+                vec!["values".to_string(), data.num_values.to_string()],
+                vec!["min".to_string(), data.min.clone()],
+                vec!["max".to_string(), data.max.clone()],
+                vec!["mean".to_string(), data.mean.clone()],
+                vec!["distinct values (estimated)".to_string(), data.distinct_values.to_string()],
+            ];
+            print_table(&["statistic", "value"], &summary);
+            println!();
+            let rows = data.percentiles.iter()
+                .map(|x| vec![x.percentile.to_string(), x.value.clone()])
+                .collect::<Vec<_>>();
+            print_table(&[if data.percentiles_exact { "percentile" } else { "percentile (sampled)" }, "value"], &rows);
+            println!();
+            let rows = data.histogram.iter()
+                .map(|x| vec![x.from.clone(), x.to.clone(), x.count.to_string(), x.percent.clone()])
+                .collect::<Vec<_>>();
+            print_table(&["from", "to", "count", "percent"], &rows);
+            if data.never_populated {
+                println!();
+                println!("Every value is 0, so this field was probably never populated.");
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tantivy::Document;
+    use tantivy::schema::SchemaBuilder;
+    use tantivy::schema::FAST;
+
+    fn assert_close(estimate: u64, actual: u64) {
+        let error = (estimate as f64 - actual as f64).abs() / actual as f64;
+        assert!(error < 0.05, "estimated {} distinct values instead of {}", estimate, actual);
+    }
+
+    #[test]
+    fn test_hyperloglog_estimate() {
+        for &num_distinct in &[10u64, 1_000, 100_000] {
+            let mut hll = HyperLogLog::new();
+            for value in 0..num_distinct {
+                hll.insert(value);
+                hll.insert(value);
+            }
+            assert_close(hll.estimate(), num_distinct);
+        }
+    }
+
+    #[test]
+    fn test_fast_field_stats() {
+        let mut schema_builder = SchemaBuilder::default();
+        let delta = schema_builder.add_i64_field("delta", FAST);
+        let index = Index::create_in_ram(schema_builder.build());
+        let mut writer = index.writer_with_num_threads(1, 40_000_000).unwrap();
+        for value in -50i64..50 {
+            let mut document = Document::default();
+            document.add_i64(delta, value);
+            writer.add_document(document);
+        }
+        writer.commit().unwrap();
+        index.load_searchers().unwrap();
+
+        let stats = fast_field_stats(&index, "delta").unwrap();
+        assert_eq!((stats.live_docs, stats.num_values, stats.docs_without_values), (100, 100, 0));
+        assert_eq!((&stats.min[..], &stats.max[..], &stats.mean[..]), ("-50", "49", "-0.50"));
+        assert_close(stats.distinct_values, 100);
+        assert!(stats.percentiles_exact);
+        let median = stats.percentiles.iter().find(|x| x.percentile == 50).unwrap();
+        assert_eq!(median.value, "-1");
+        assert_eq!(stats.histogram.len(), NUM_BUCKETS);
+        assert_eq!(stats.histogram.iter().map(|x| x.count).sum::<u64>(), 100);
+        assert!(!stats.never_populated);
+    }
+}
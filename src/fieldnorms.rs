@@ -0,0 +1,197 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::collections::BinaryHeap;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use actix_web::Query;
+use failure::err_msg;
+use failure::Error;
+use tantivy::DocId;
+use tantivy::Index;
+
+use output::print_json;
+use output::print_table;
+use output::OutputFormat;
+use State;
+
+#[derive(Serialize)]
+pub struct HistogramBucket {
+    length: u32,
+    count: u64,
+    percent: String,
+}
+
+#[derive(Serialize)]
+pub struct DocLength {
+    segment: String,
+    doc: DocId,
+    length: u32,
+}
+
+#[derive(Serialize)]
+pub struct FieldnormsData {
+    field: String,
+    live_docs: u64,
+    average: String,
+    histogram: Vec<HistogramBucket>,
+    longest: Vec<DocLength>,
+    shortest: Vec<DocLength>,
+}
+
+/// Pushes onto a heap holding at most `k` entries, dropping the greatest.
+fn push_bounded<T: Ord>(heap: &mut BinaryHeap<T>, k: usize, value: T) {
+    if heap.len() < k {
+        heap.push(value);
+    } else if let Some(mut top) = heap.peek_mut() {
+        if value < *top {
+            *top = value;
+        }
+    }
+}
+
+fn to_doc_lengths(entries: Vec<(u32, String, DocId)>) -> Vec<DocLength> {
+    entries.into_iter()
+        .map(|(length, segment, doc)| DocLength { segment, doc, length })
+        .collect()
+}
+
+/// Token counts per doc for a field, as recorded in its fieldnorms.
+pub fn fieldnorms(index: &Index, field_name: &str, k: usize) -> Result<FieldnormsData, Error> {
+    let schema = index.schema();
+    let field = schema.get_field(field_name).ok_or(err_msg("Sorry, that field does not exist!"))?;
+    if schema.get_field_entry(field).field_type().get_index_record_option().is_none() {
+        return Err(err_msg("Only indexed fields have fieldnorms"));
+    }
+    let searcher = index.searcher();
+
+    let mut counts = BTreeMap::new();
+    let mut live_docs = 0u64;
+    let mut total_length = 0u64;
+    let mut longest = BinaryHeap::new();
+    let mut shortest = BinaryHeap::new();
+
+    for segment in searcher.segment_readers() {
+        let reader = segment.get_fieldnorms_reader(field);
+        let segment_id = segment.segment_id().uuid_string();
+        for doc in 0..segment.max_doc() {
+            if segment.is_deleted(doc) {
+                continue;
+            }
+            let length = reader.fieldnorm(doc);
+            *counts.entry(length).or_insert(0u64) += 1;
+            live_docs += 1;
+            total_length += length as u64;
+            push_bounded(&mut longest, k, Reverse((length, segment_id.clone(), doc)));
+            push_bounded(&mut shortest, k, (length, segment_id.clone(), doc));
+        }
+    }
+
+    let max_count = counts.values().cloned().max().unwrap_or(0);
+    let histogram = counts.into_iter()
+        .map(|(length, count)| HistogramBucket {
+            length,
+            count,
+            percent: format!("{:.1}", count as f64 * 100.0 / max_count as f64),
+        })
+        .collect();
+
+    let average = if live_docs == 0 {
+        "-".to_string()
+    } else {
+        format!("{:.2}", total_length as f64 / live_docs as f64)
+    };
+
+    Ok(FieldnormsData {
+        field: field_name.to_string(),
+        live_docs,
+        average,
+        histogram,
+        longest: to_doc_lengths(longest.into_sorted_vec().into_iter().map(|Reverse(x)| x).collect()),
+        shortest: to_doc_lengths(shortest.into_sorted_vec()),
+    })
+}
+
+#[derive(Deserialize)]
+pub struct FieldnormsQuery {
+    field: String,
+    k: Option<usize>,
+}
+
+pub(crate) fn handle_fieldnorms(req: (HttpRequest<State>, Query<FieldnormsQuery>)) -> Result<HttpResponse, Error> {
+    let (req, params) = req;
+    let state = req.state();
+    let data = fieldnorms(&state.index, &params.field, params.k.unwrap_or(20))?;
+    Ok(state.render_template("fieldnorms", &data)?)
+}
+
+fn doc_length_rows(lengths: &[DocLength]) -> Vec<Vec<String>> {
+    lengths.iter()
+        .map(|x| vec![x.segment.clone(), x.doc.to_string(), x.length.to_string()])
+        .collect()
+}
+
+pub fn run_fieldnorms(index: &Index, field_name: &str, k: usize, format: OutputFormat) -> Result<(), Error> {
+    let data = fieldnorms(index, field_name, k)?;
+    match format {
+        OutputFormat::Json => print_json(&data)?,
+        OutputFormat::Table => {
+            println!("Live docs: {}", data.live_docs);
+            println!("Average length: {}", data.average);
+            println!();
+            let rows = data.histogram.iter()
+                .map(|x| vec![x.length.to_string(), x.count.to_string(), x.percent.clone()])
+                .collect::<Vec<_>>();
+            print_table(&["length", "count", "percent"], &rows);
+            println!();
+            println!("Longest docs:");
+            print_table(&["segment", "doc", "length"], &doc_length_rows(&data.longest));
+            println!();
+            println!("Shortest docs:");
+            print_table(&["segment", "doc", "length"], &doc_length_rows(&data.shortest));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tantivy::Document;
+    use tantivy::schema::SchemaBuilder;
+    use tantivy::schema::STRING;
+    use tantivy::schema::TEXT;
+
+    #[test]
+    fn test_push_bounded_keeps_the_smallest() {
+        let mut heap = BinaryHeap::new();
+        for value in vec![5, 1, 4, 2, 3] {
+            push_bounded(&mut heap, 3, value);
+        }
+        assert_eq!(heap.into_sorted_vec(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_fieldnorms() {
+        let mut schema_builder = SchemaBuilder::default();
+        let body = schema_builder.add_text_field("body", TEXT);
+        schema_builder.add_text_field("id", STRING);
+        let index = Index::create_in_ram(schema_builder.build());
+        let mut writer = index.writer_with_num_threads(1, 40_000_000).unwrap();
+        for text in vec!["one", "one two", "one two three", "four five", "six"] {
+            let mut document = Document::default();
+            document.add_text(body, text);
+            writer.add_document(document);
+        }
+        writer.commit().unwrap();
+        index.load_searchers().unwrap();
+
+        let data = fieldnorms(&index, "body", 2).unwrap();
+        assert_eq!(data.live_docs, 5);
+        assert_eq!(data.average, "1.80");
+        let histogram = data.histogram.iter().map(|x| (x.length, x.count)).collect::<Vec<_>>();
+        assert_eq!(histogram, vec![(1, 2), (2, 2), (3, 1)]);
+        assert_eq!(data.longest.iter().map(|x| (x.doc, x.length)).collect::<Vec<_>>(), vec![(2, 3), (3, 2)]);
+        assert_eq!(data.shortest.iter().map(|x| (x.doc, x.length)).collect::<Vec<_>>(), vec![(0, 1), (4, 1)]);
+        assert!(fieldnorms(&index, "missing", 2).is_err());
+    }
+}
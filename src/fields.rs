@@ -16,6 +16,8 @@ pub struct FieldDescriptor {
     pub name: String,
     pub value_type: Type,
     pub extra_options: Value,
+    pub indexed: bool,
+    pub numeric_fast: bool,
 }
 
 fn field_options(field_type: &FieldType) -> Result<Value> {
@@ -28,6 +30,13 @@ fn field_options(field_type: &FieldType) -> Result<Value> {
     })
 }
 
+fn is_numeric_fast(field_type: &FieldType) -> bool {
+    match *field_type {
+        FieldType::U64(ref options) | FieldType::I64(ref options) => options.is_fast(),
+        _ => false,
+    }
+}
+
 pub fn get_fields(index: &Index) -> Result<Fields> {
     let schema = index.schema();
 
@@ -39,6 +48,8 @@ pub fn get_fields(index: &Index) -> Result<Fields> {
             name,
             value_type: field.field_type().value_type(),
             extra_options: field_options(field.field_type())?,
+            indexed: field.field_type().get_index_record_option().is_some(),
+            numeric_fast: is_numeric_fast(field.field_type()),
         });
     }
 
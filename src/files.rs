@@ -0,0 +1,178 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use failure::Error;
+use tantivy::Index;
+
+use output::print_json;
+use output::print_table;
+use output::OutputFormat;
+use State;
+use TantivyViewerError;
+
+/// A file found in the index directory.
+#[derive(Serialize, Clone)]
+pub struct IndexFile {
+    pub name: String,
+    pub size: u64,
+    pub segment: Option<String>,
+    pub extension: Option<String>,
+}
+
+fn is_segment_uuid(s: &str) -> bool {
+    s.len() == 32 && s.chars().all(|c| c.is_digit(16))
+}
+
+impl IndexFile {
+    fn from_name(name: String, size: u64) -> IndexFile {
+        // Segment files are named `<uuid>.<ext>`, except deletes which are `<uuid>.<opstamp>.del`.
+        let mut parts = name.split('.');
+        let segment = parts.next()
+            .filter(|x| is_segment_uuid(x))
+            .map(|x| x.to_string());
+        let extension = if segment.is_some() {
+            parts.last().map(|x| x.to_string())
+        } else {
+            None
+        };
+        IndexFile {
+            name,
+            size,
+            segment,
+            extension,
+        }
+    }
+}
+
+pub fn list_index_files(path: &Path) -> io::Result<Vec<IndexFile>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if !metadata.is_file() {
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy().into_owned();
+        files.push(IndexFile::from_name(name, metadata.len()));
+    }
+    files.sort_by(|x, y| x.name.cmp(&y.name));
+    Ok(files)
+}
+
+#[derive(Serialize)]
+pub struct FileEntry {
+    file: IndexFile,
+    referenced: bool,
+}
+
+#[derive(Serialize)]
+pub struct FilesData {
+    files: Vec<FileEntry>,
+    total_bytes: u64,
+    referenced_bytes: u64,
+    unreferenced_bytes: u64,
+    other_bytes: u64,
+    space_usage_total: usize,
+    /// Referenced bytes on disk minus what the searcher accounts for.
+    space_usage_gap: i64,
+    space_usage_reconciled: bool,
+}
+
+pub fn files(index: &Index, index_path: &Path) -> Result<FilesData, Error> {
+    let referenced = index.searchable_segment_metas()
+        .map_err(TantivyViewerError::TantivyError)?
+        .into_iter()
+        .flat_map(|meta| meta.list_files().into_iter())
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect::<HashSet<_>>();
+    let space_usage_total = index.searcher().space_usage().total();
+
+    let mut total_bytes = 0;
+    let mut referenced_bytes = 0;
+    let mut unreferenced_bytes = 0;
+    let mut other_bytes = 0;
+    let mut files = Vec::new();
+    for file in list_index_files(index_path)? {
+        let is_referenced = referenced.contains(&file.name);
+        total_bytes += file.size;
+        if is_referenced {
+            referenced_bytes += file.size;
+        } else if file.segment.is_some() {
+            unreferenced_bytes += file.size;
+        } else {
+            other_bytes += file.size;
+        }
+        files.push(FileEntry {
+            referenced: is_referenced || file.segment.is_none(),
+            file,
+        });
+    }
+
+    let space_usage_gap = referenced_bytes as i64 - space_usage_total as i64;
+    Ok(FilesData {
+        files,
+        total_bytes,
+        referenced_bytes,
+        unreferenced_bytes,
+        other_bytes,
+        space_usage_total,
+        space_usage_gap,
+        space_usage_reconciled: space_usage_gap == 0,
+    })
+}
+
+pub(crate) fn handle_files(req: HttpRequest<State>) -> Result<HttpResponse, Error> {
+    let state = req.state();
+    let data = files(&state.index, &state.index_path)?;
+    Ok(state.render_template("files", &data)?)
+}
+
+pub fn run_files(index: &Index, index_path: &Path, format: OutputFormat) -> Result<(), Error> {
+    let data = files(index, index_path)?;
+    match format {
+        OutputFormat::Json => print_json(&data)?,
+        OutputFormat::Table => {
+            let rows = data.files.iter()
+                .map(|x| vec![
+                    x.file.name.clone(),
+                    x.file.size.to_string(),
+                    if x.referenced { "" } else { "unreferenced" }.to_string(),
+                ])
+                .collect::<Vec<_>>();
+            print_table(&["file", "bytes", ""], &rows);
+            println!();
+            println!("Total: {} bytes", data.total_bytes);
+            println!("Referenced by searchable segments: {} bytes", data.referenced_bytes);
+            println!("Segment files not referenced by any searchable segment: {} bytes", data.unreferenced_bytes);
+            println!("Other files: {} bytes", data.other_bytes);
+            println!("Space usage reported by the searcher: {} bytes", data.space_usage_total);
+            if !data.space_usage_reconciled {
+                println!("Referenced bytes not accounted for by the searcher: {} bytes", data.space_usage_gap);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parts(name: &str) -> (Option<String>, Option<String>) {
+        let file = IndexFile::from_name(name.to_string(), 0);
+        (file.segment, file.extension)
+    }
+
+    #[test]
+    fn test_from_name() {
+        let segment = "0123456789abcdef0123456789abcdef".to_string();
+        assert_eq!(parts(&format!("{}.idx", segment)), (Some(segment.clone()), Some("idx".to_string())));
+        assert_eq!(parts(&format!("{}.12.del", segment)), (Some(segment.clone()), Some("del".to_string())));
+        assert_eq!(parts("meta.json"), (None, None));
+        assert_eq!(parts("0123456789abcdef.idx"), (None, None));
+        assert_eq!(parts(".managed.json"), (None, None));
+    }
+}
@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use failure::Error;
+use tantivy::Index;
+use tantivy::schema::Field;
+use tantivy::schema::FieldEntry;
+use tantivy::schema::FieldType;
+use tantivy::schema::Value;
+
+use audit::sample_docs;
+use output::print_json;
+use output::print_table;
+use output::OutputFormat;
+use space_usage::index_stats;
+use space_usage::FieldStats;
+use top_terms::for_each_merged_term;
+use State;
+use TantivyViewerError;
+
+/// Docs sampled per segment when apportioning doc store bytes between fields.
+const STORE_SAMPLE: usize = 1000;
+
+#[derive(Serialize)]
+pub struct Finding {
+    field: String,
+    rule: String,
+    message: String,
+    estimated_savings: usize,
+}
+
+#[derive(Serialize)]
+pub struct LintData {
+    findings: Vec<Finding>,
+    estimated_savings: usize,
+}
+
+fn component(stats: Option<&FieldStats>, name: &str) -> usize {
+    stats.and_then(|x| x.components.get(name)).cloned().unwrap_or(0)
+}
+
+/// Positions are useless when no doc has more than one token in the field.
+fn lint_single_token_positions(index: &Index, field: Field, field_entry: &FieldEntry, stats: Option<&FieldStats>) -> Option<Finding> {
+    let has_positions = field_entry.field_type().get_index_record_option().map(|x| x.has_positions()).unwrap_or(false);
+    if !has_positions {
+        return None;
+    }
+    let searcher = index.searcher();
+    let mut max_length = 0u32;
+    for segment in searcher.segment_readers() {
+        let reader = segment.get_fieldnorms_reader(field);
+        for doc in 0..segment.max_doc() {
+            if !segment.is_deleted(doc) {
+                max_length = max_length.max(reader.fieldnorm(doc));
+            }
+        }
+    }
+    if max_length != 1 {
+        return None;
+    }
+    Some(Finding {
+        field: field_entry.name().to_string(),
+        rule: "Positions on a single-token field".to_string(),
+        message: "Every doc has at most one token in this field, so positions and frequencies carry no information. Index it with the basic record option.".to_string(),
+        estimated_savings: component(stats, "positions"),
+    })
+}
+
+/// Numeric fields that are indexed but not fast can't be sorted or scored on cheaply.
+fn lint_indexed_not_fast(num_docs: u64, field_entry: &FieldEntry, stats: Option<&FieldStats>) -> Option<Finding> {
+    let options = match *field_entry.field_type() {
+        FieldType::U64(ref options) | FieldType::I64(ref options) => options,
+        _ => return None,
+    };
+    if !options.is_indexed() || options.is_fast() {
+        return None;
+    }
+    // A fast field needs at most 8 bytes per doc.
+    let inverted_bytes = component(stats, "termdict") + component(stats, "postings") + component(stats, "fieldnorms");
+    Some(Finding {
+        field: field_entry.name().to_string(),
+        rule: "Numeric field indexed but never fast".to_string(),
+        message: "If this field is only used for sorting, scoring or range filtering, making it fast instead of indexed is usually cheaper and quicker.".to_string(),
+        estimated_savings: inverted_bytes.saturating_sub(num_docs as usize * 8),
+    })
+}
+
+/// Text fields holding only numbers would be smaller and range-queryable as numeric fields.
+fn lint_numeric_text(index: &Index, field: Field, field_entry: &FieldEntry, stats: Option<&FieldStats>) -> Option<Finding> {
+    match *field_entry.field_type() {
+        FieldType::Str(_) if field_entry.field_type().get_index_record_option().is_some() => {},
+        _ => return None,
+    }
+    let mut num_terms = 0;
+    let mut all_numeric = true;
+    for_each_merged_term(index, field, |key, _doc_freq| {
+        num_terms += 1;
+        let text = String::from_utf8_lossy(key);
+        all_numeric = all_numeric && (text.parse::<i64>().is_ok() || text.parse::<u64>().is_ok());
+    });
+    if num_terms == 0 || !all_numeric {
+        return None;
+    }
+    Some(Finding {
+        field: field_entry.name().to_string(),
+        rule: "Text field with only numeric terms".to_string(),
+        message: "Every term in this field is a number. A U64 or I64 field would drop positions and fieldnorms and support range queries.".to_string(),
+        estimated_savings: component(stats, "positions") + component(stats, "fieldnorms"),
+    })
+}
+
+fn value_size(value: &Value) -> usize {
+    match *value {
+        Value::Str(ref text) => text.len(),
+        Value::U64(_) | Value::I64(_) => 8,
+        Value::Facet(ref facet) => format!("{}", facet).len(),
+        Value::Bytes(ref bytes) => bytes.len(),
+    }
+}
+
+/// Estimates how the doc store is split between fields by sampling stored docs.
+fn stored_field_shares(index: &Index) -> Result<HashMap<Field, f64>, Error> {
+    let searcher = index.searcher();
+    let mut sizes = HashMap::new();
+    let mut total = 0;
+    for segment in searcher.segment_readers() {
+        for doc in sample_docs(segment, STORE_SAMPLE) {
+            let doc = segment.doc(doc).map_err(TantivyViewerError::TantivyError)?;
+            for field_value in doc.field_values() {
+                let size = value_size(field_value.value());
+                *sizes.entry(field_value.field()).or_insert(0) += size;
+                total += size;
+            }
+        }
+    }
+    Ok(sizes.into_iter()
+        .map(|(field, size)| (field, if total == 0 { 0.0 } else { size as f64 / total as f64 }))
+        .collect())
+}
+
+fn lint_store_share(field_entry: &FieldEntry, share: f64, num_stored_fields: usize, store_bytes: usize) -> Option<Finding> {
+    let fair_share = 1.0 / num_stored_fields as f64;
+    if num_stored_fields < 2 || share < 0.25 || share < 2.0 * fair_share {
+        return None;
+    }
+    Some(Finding {
+        field: field_entry.name().to_string(),
+        rule: "Stored field dominates the doc store".to_string(),
+        message: format!("This field accounts for about {:.0}% of stored bytes. Consider not storing it, or storing it elsewhere.", share * 100.0),
+        estimated_savings: (share * store_bytes as f64) as usize,
+    })
+}
+
+/// Cross-references each field's options with what its data actually looks like.
+pub fn lint(index: &Index) -> Result<LintData, Error> {
+    let schema = index.schema();
+    let stats = index_stats(index)?;
+    let store_bytes = stats.components.get("store").cloned().unwrap_or(0);
+    let shares = stored_field_shares(index)?;
+    let num_stored_fields = schema.fields().iter().filter(|x| x.is_stored()).count();
+
+    let mut findings = Vec::new();
+    for (idx, field_entry) in schema.fields().iter().enumerate() {
+        let field = Field(idx as u32);
+        let field_stats = stats.fields.get(field_entry.name());
+        findings.extend(lint_single_token_positions(index, field, field_entry, field_stats));
+        findings.extend(lint_indexed_not_fast(stats.num_docs, field_entry, field_stats));
+        findings.extend(lint_numeric_text(index, field, field_entry, field_stats));
+        if let Some(&share) = shares.get(&field) {
+            findings.extend(lint_store_share(field_entry, share, num_stored_fields, store_bytes));
+        }
+    }
+    findings.sort_by(|x, y| y.estimated_savings.cmp(&x.estimated_savings));
+
+    Ok(LintData {
+        estimated_savings: findings.iter().map(|x| x.estimated_savings).sum(),
+        findings,
+    })
+}
+
+pub(crate) fn handle_lint(req: HttpRequest<State>) -> Result<HttpResponse, Error> {
+    let state = req.state();
+    let data = lint(&state.index)?;
+    Ok(state.render_template("lint", &data)?)
+}
+
+pub fn run_lint(index: &Index, format: OutputFormat) -> Result<(), Error> {
+    let data = lint(index)?;
+    match format {
+        OutputFormat::Json => print_json(&data)?,
+        OutputFormat::Table => {
+            let rows = data.findings.iter()
+                .map(|x| vec![x.field.clone(), x.rule.clone(), x.estimated_savings.to_string(), x.message.clone()])
+                .collect::<Vec<_>>();
+            print_table(&["field", "rule", "estimated savings", "message"], &rows);
+            println!();
+            println!("Estimated savings: {} bytes", data.estimated_savings);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tantivy::Document;
+    use tantivy::schema::SchemaBuilder;
+    use tantivy::schema::INT_INDEXED;
+    use tantivy::schema::STORED;
+    use tantivy::schema::TEXT;
+
+    #[test]
+    fn test_lint_findings() {
+        let mut schema_builder = SchemaBuilder::default();
+        let code = schema_builder.add_text_field("code", TEXT);
+        let count = schema_builder.add_u64_field("count", INT_INDEXED);
+        let body = schema_builder.add_text_field("body", TEXT | STORED);
+        let title = schema_builder.add_text_field("title", STORED);
+        let index = Index::create_in_ram(schema_builder.build());
+        let mut writer = index.writer_with_num_threads(1, 40_000_000).unwrap();
+        for i in 0..10u64 {
+            let mut document = Document::default();
+            document.add_text(code, &format!("{}", 1000 + i));
+            document.add_u64(count, i);
+            document.add_text(body, "a long body of text that takes up most of the stored bytes of every doc");
+            document.add_text(title, "short");
+            writer.add_document(document);
+        }
+        writer.commit().unwrap();
+        index.load_searchers().unwrap();
+
+        let mut findings = lint(&index).unwrap().findings.into_iter()
+            .map(|x| (x.field, x.rule))
+            .collect::<Vec<_>>();
+        findings.sort();
+        let expected = vec![
+            ("body", "Stored field dominates the doc store"),
+            ("code", "Positions on a single-token field"),
+            ("code", "Text field with only numeric terms"),
+            ("count", "Numeric field indexed but never fast"),
+        ];
+        assert_eq!(findings, expected.into_iter().map(|(x, y)| (x.to_string(), y.to_string())).collect::<Vec<_>>());
+    }
+}
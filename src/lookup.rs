@@ -0,0 +1,454 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use actix_web::Query;
+use actix_web::http;
+use failure::err_msg;
+use failure::Error;
+use tantivy::DocId;
+use tantivy::DocSet;
+use tantivy::Index;
+use tantivy::Searcher;
+use tantivy::SegmentId;
+use tantivy::Term;
+use tantivy::schema::Field;
+use tantivy::schema::FieldType;
+use tantivy::schema::IndexRecordOption;
+
+use get_identifying_fields;
+use output::print_json;
+use output::print_table;
+use output::OutputFormat;
+use reconstruct::reconstruct;
+use reconstruct::tokenize;
+use stringify_values;
+use top_terms::TantivyValue;
+use State;
+
+/// Builds the term a value would have been indexed as.
+///
+/// Text values go through the field's tokenizer and must come out as a single token,
+/// which is what identifier fields normally look like.
+fn value_term(index: &Index, field: Field, value: &str) -> Result<Term, Error> {
+    let schema = index.schema();
+    let field_type = schema.get_field_entry(field).field_type();
+    if field_type.get_index_record_option().is_none() {
+        return Err(err_msg("Only indexed fields can be looked up by value"));
+    }
+    match *field_type {
+        FieldType::Str(ref options) => {
+            let mut tokens = tokenize(index, options, value)?;
+            if tokens.len() != 1 {
+                return Err(err_msg(format!("'{}' is not a single term in this field", value)));
+            }
+            Ok(Term::from_field_text(field, &tokens.pop().unwrap().text))
+        }
+        FieldType::U64(_) => Ok(Term::from_field_u64(field, value.parse::<u64>()?)),
+        FieldType::I64(_) => Ok(Term::from_field_i64(field, value.parse::<i64>()?)),
+        _ => Err(err_msg("Only text and numeric fields can be looked up by value")),
+    }
+}
+
+/// Live docs containing `term`, in searcher order.
+fn live_matches(searcher: &Searcher, field: Field, term: &Term) -> Vec<(SegmentId, DocId)> {
+    let mut matches = Vec::new();
+    for segment in searcher.segment_readers() {
+        if let Some(mut postings) = segment.inverted_index(field).read_postings(term, IndexRecordOption::Basic) {
+            while postings.advance() {
+                if !segment.is_deleted(postings.doc()) {
+                    matches.push((segment.segment_id(), postings.doc()));
+                }
+            }
+        }
+    }
+    matches
+}
+
+fn get_field(index: &Index, field_name: &str) -> Result<Field, Error> {
+    index.schema().get_field(field_name).ok_or(err_msg("Sorry, that field does not exist!"))
+}
+
+fn indexed_fields(index: &Index) -> Vec<String> {
+    let mut fields = index.schema()
+        .fields()
+        .iter()
+        .filter(|x| x.field_type().get_index_record_option().is_some())
+        .map(|x| x.name().to_string())
+        .collect::<Vec<_>>();
+    fields.sort();
+    fields
+}
+
+#[derive(Serialize)]
+pub struct DocMatch {
+    segment: String,
+    doc: DocId,
+    identifying_values: Vec<String>,
+}
+
+impl DocMatch {
+    pub fn doc(&self) -> DocId {
+        self.doc
+    }
+
+    pub fn identifying_values(&self) -> &[String] {
+        &self.identifying_values
+    }
+}
+
+/// Reconstructs the identifying fields of each match, keeping the order of `matches`.
+///
+/// Reconstruction wants each segment's docs sorted, so the values are looked up by
+/// address afterwards rather than by position.
+pub(crate) fn describe_matches<R>(identifying_fields: &[String], matches: Vec<(SegmentId, DocId)>, reconstruct_field: R) -> Result<Vec<DocMatch>, Error>
+    where R: Fn(&str, &HashMap<SegmentId, Vec<DocId>>) -> Result<HashMap<SegmentId, Vec<(DocId, Vec<Option<TantivyValue>>)>>, Error> {
+    let mut docs = HashMap::new();
+    for &(segment, doc) in matches.iter() {
+        docs.entry(segment).or_insert_with(Vec::new).push(doc);
+    }
+    for segment_docs in docs.values_mut() {
+        segment_docs.sort();
+        segment_docs.dedup();
+    }
+
+    let mut reconstructed_fields = Vec::new();
+    for field in identifying_fields {
+        let reconstructed = reconstruct_field(field, &docs)?
+            .into_iter()
+            .flat_map(|(segment, docs)| docs.into_iter().map(move |(doc, values)| ((segment, doc), values)))
+            .collect::<HashMap<_, _>>();
+        reconstructed_fields.push(reconstructed);
+    }
+
+    let mut result = Vec::new();
+    for (segment, doc) in matches.into_iter() {
+        let identifying_values = reconstructed_fields.iter()
+            .map(|field| stringify_values(field.get(&(segment, doc)).cloned().unwrap_or_default()))
+            .collect();
+        result.push(DocMatch {
+            segment: segment.uuid_string(),
+            doc,
+            identifying_values,
+        });
+    }
+    Ok(result)
+}
+
+#[derive(Deserialize)]
+pub struct DocLookupQuery {
+    field: Option<String>,
+    value: Option<String>,
+}
+
+#[derive(Serialize)]
+struct DocLookupData {
+    fields: Vec<String>,
+    field: String,
+    value: String,
+    searched: bool,
+    identifying_fields: Vec<String>,
+    matches: Vec<DocMatch>,
+}
+
+/// Live docs whose `field_name` holds `value`, in searcher order.
+pub fn lookup(index: &Index, field_name: &str, value: &str) -> Result<Vec<(SegmentId, DocId)>, Error> {
+    let field = get_field(index, field_name)?;
+    let term = value_term(index, field, value)?;
+    let searcher = index.searcher();
+    Ok(live_matches(&*searcher, field, &term))
+}
+
+pub(crate) fn handle_doc_lookup(req: (HttpRequest<State>, Query<DocLookupQuery>)) -> Result<HttpResponse, Error> {
+    let (req, params) = req;
+    let state = req.state();
+    let mut data = DocLookupData {
+        fields: indexed_fields(&state.index),
+        field: String::new(),
+        value: String::new(),
+        searched: false,
+        identifying_fields: Vec::new(),
+        matches: Vec::new(),
+    };
+
+    let (field_name, value) = match (&params.field, &params.value) {
+        (&Some(ref field), &Some(ref value)) => (field.clone(), value.clone()),
+        _ => return Ok(state.render_template("doc_lookup", &data)?),
+    };
+
+    let matches = lookup(&state.index, &field_name, &value)?;
+    if matches.len() == 1 {
+        let (segment, doc) = matches[0];
+        return Ok(
+            HttpResponse::Found()
+                .header(http::header::LOCATION, format!("reconstruct?segment={}&doc={}", segment.uuid_string(), doc))
+                .finish()
+        );
+    }
+
+    let identifying_fields = get_identifying_fields(&req);
+    data.matches = describe_matches(&identifying_fields, matches, |field, docs| state.reconstruct(field, docs))?;
+    data.identifying_fields = identifying_fields;
+    data.field = field_name;
+    data.value = value;
+    data.searched = true;
+    Ok(state.render_template("doc_lookup", &data)?)
+}
+
+#[derive(Serialize)]
+struct LookupResultData {
+    field: String,
+    value: String,
+    identifying_fields: Vec<String>,
+    matches: Vec<DocMatch>,
+}
+
+pub fn run_lookup(index: &Index, field_name: &str, value: &str, identifying_fields: Vec<String>, format: OutputFormat) -> Result<(), Error> {
+    let matches = lookup(index, field_name, value)?;
+    let matches = describe_matches(&identifying_fields, matches, |field, docs| reconstruct(index, field, docs))?;
+    match format {
+        OutputFormat::Json => print_json(&LookupResultData {
+            field: field_name.to_string(),
+            value: value.to_string(),
+            identifying_fields,
+            matches,
+        })?,
+        OutputFormat::Table => {
+            let mut headers = vec!["segment", "doc"];
+            headers.extend(identifying_fields.iter().map(|x| &x[..]));
+            let rows = matches.into_iter()
+                .map(|x| {
+                    let mut row = vec![x.segment, x.doc.to_string()];
+                    row.extend(x.identifying_values.into_iter().map(|x| x.trim_right().to_string()));
+                    row
+                })
+                .collect::<Vec<_>>();
+            print_table(&headers, &rows);
+        }
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct DocBulkQuery {
+    field: Option<String>,
+    values: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct FoundValue {
+    value: String,
+    segment: String,
+    doc: DocId,
+}
+
+#[derive(Serialize)]
+pub struct DuplicatedValue {
+    value: String,
+    docs: Vec<(String, DocId)>,
+}
+
+#[derive(Serialize)]
+pub struct InvalidValue {
+    value: String,
+    error: String,
+}
+
+/// The outcome of looking up many values of one field at once.
+#[derive(Serialize)]
+pub struct BulkLookup {
+    found: Vec<FoundValue>,
+    missing: Vec<String>,
+    duplicated: Vec<DuplicatedValue>,
+    invalid: Vec<InvalidValue>,
+}
+
+/// Looks up each non-empty line of `values`, sorting them by how many live docs hold them.
+pub fn bulk_lookup(index: &Index, field_name: &str, values: &str) -> Result<BulkLookup, Error> {
+    let field = get_field(index, field_name)?;
+    let searcher = index.searcher();
+    let mut result = BulkLookup {
+        found: Vec::new(),
+        missing: Vec::new(),
+        duplicated: Vec::new(),
+        invalid: Vec::new(),
+    };
+    for value in values.lines().map(|x| x.trim()).filter(|x| !x.is_empty()) {
+        let term = match value_term(index, field, value) {
+            Ok(term) => term,
+            Err(e) => {
+                result.invalid.push(InvalidValue { value: value.to_string(), error: format!("{}", e) });
+                continue;
+            }
+        };
+        let mut matches = live_matches(&*searcher, field, &term);
+        match matches.len() {
+            0 => result.missing.push(value.to_string()),
+            1 => {
+                let (segment, doc) = matches.pop().unwrap();
+                result.found.push(FoundValue { value: value.to_string(), segment: segment.uuid_string(), doc });
+            }
+            _ => result.duplicated.push(DuplicatedValue {
+                value: value.to_string(),
+                docs: matches.into_iter().map(|(segment, doc)| (segment.uuid_string(), doc)).collect(),
+            }),
+        }
+    }
+    Ok(result)
+}
+
+#[derive(Serialize)]
+struct DocBulkData {
+    fields: Vec<String>,
+    field: String,
+    values: String,
+    searched: bool,
+    found: Vec<FoundValue>,
+    missing: Vec<String>,
+    duplicated: Vec<DuplicatedValue>,
+    invalid: Vec<InvalidValue>,
+    num_found: usize,
+    num_missing: usize,
+    num_duplicated: usize,
+    num_invalid: usize,
+}
+
+pub(crate) fn handle_doc_bulk(req: (HttpRequest<State>, Query<DocBulkQuery>)) -> Result<HttpResponse, Error> {
+    let (req, params) = req;
+    let state = req.state();
+    let mut data = DocBulkData {
+        fields: indexed_fields(&state.index),
+        field: String::new(),
+        values: String::new(),
+        searched: false,
+        found: Vec::new(),
+        missing: Vec::new(),
+        duplicated: Vec::new(),
+        invalid: Vec::new(),
+        num_found: 0,
+        num_missing: 0,
+        num_duplicated: 0,
+        num_invalid: 0,
+    };
+
+    let (field_name, values) = match (&params.field, &params.values) {
+        (&Some(ref field), &Some(ref values)) => (field.clone(), values.clone()),
+        _ => return Ok(state.render_template("doc_bulk", &data)?),
+    };
+
+    let result = bulk_lookup(&state.index, &field_name, &values)?;
+    data.num_found = result.found.len();
+    data.num_missing = result.missing.len();
+    data.num_duplicated = result.duplicated.len();
+    data.num_invalid = result.invalid.len();
+    data.found = result.found;
+    data.missing = result.missing;
+    data.duplicated = result.duplicated;
+    data.invalid = result.invalid;
+    data.field = field_name;
+    data.values = values;
+    data.searched = true;
+    Ok(state.render_template("doc_bulk", &data)?)
+}
+
+/// Looks up the values listed one per line in `values_path`, or on stdin without one.
+pub fn run_bulk_lookup(index: &Index, field_name: &str, values_path: Option<&Path>, format: OutputFormat) -> Result<(), Error> {
+    let values = match values_path {
+        Some(path) => fs::read_to_string(path)?,
+        None => {
+            let mut values = String::new();
+            io::stdin().read_to_string(&mut values)?;
+            values
+        }
+    };
+    let result = bulk_lookup(index, field_name, &values)?;
+    match format {
+        OutputFormat::Json => print_json(&result)?,
+        OutputFormat::Table => {
+            let mut rows = Vec::new();
+            for x in result.found {
+                rows.push(vec![x.value, "found".to_string(), x.segment, x.doc.to_string()]);
+            }
+            for x in result.duplicated {
+                for (segment, doc) in x.docs {
+                    rows.push(vec![x.value.clone(), "duplicated".to_string(), segment, doc.to_string()]);
+                }
+            }
+            for x in result.missing {
+                rows.push(vec![x, "missing".to_string(), "-".to_string(), "-".to_string()]);
+            }
+            for x in result.invalid {
+                rows.push(vec![x.value, format!("invalid: {}", x.error), "-".to_string(), "-".to_string()]);
+            }
+            print_table(&["value", "result", "segment", "doc"], &rows);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tantivy::Document;
+    use tantivy::schema::SchemaBuilder;
+    use tantivy::schema::INT_INDEXED;
+    use tantivy::schema::STORED;
+    use tantivy::schema::STRING;
+    use tantivy::schema::TEXT;
+
+    fn test_index() -> Index {
+        let mut schema_builder = SchemaBuilder::default();
+        let id = schema_builder.add_text_field("id", STRING | STORED);
+        let title = schema_builder.add_text_field("title", TEXT);
+        let count = schema_builder.add_u64_field("count", INT_INDEXED);
+        schema_builder.add_text_field("notes", STORED);
+        let index = Index::create_in_ram(schema_builder.build());
+        let mut writer = index.writer_with_num_threads(1, 40_000_000).unwrap();
+        for &(id_value, title_value, count_value) in &[("x", "First Doc", 1u64), ("y", "Second doc", 2), ("y", "third", 2)] {
+            let mut document = Document::default();
+            document.add_text(id, id_value);
+            document.add_text(title, title_value);
+            document.add_u64(count, count_value);
+            writer.add_document(document);
+        }
+        writer.commit().unwrap();
+        index.load_searchers().unwrap();
+        index
+    }
+
+    #[test]
+    fn test_lookup() {
+        let index = test_index();
+        assert_eq!(lookup(&index, "id", "x").unwrap().iter().map(|x| x.1).collect::<Vec<_>>(), vec![0]);
+        assert_eq!(lookup(&index, "count", "2").unwrap().iter().map(|x| x.1).collect::<Vec<_>>(), vec![1, 2]);
+        // Text values go through the field's tokenizer, so case doesn't matter here.
+        assert_eq!(lookup(&index, "title", "THIRD").unwrap().len(), 1);
+        assert!(lookup(&index, "title", "second doc").is_err());
+        assert!(lookup(&index, "count", "two").is_err());
+        assert!(lookup(&index, "notes", "x").is_err());
+        assert!(lookup(&index, "missing", "x").is_err());
+    }
+
+    #[test]
+    fn test_bulk_lookup() {
+        let index = test_index();
+        let result = bulk_lookup(&index, "count", "1\n 2 \n\n3\nfour\n").unwrap();
+        assert_eq!(result.found.iter().map(|x| (&x.value[..], x.doc)).collect::<Vec<_>>(), vec![("1", 0)]);
+        assert_eq!(result.duplicated.iter().map(|x| (&x.value[..], x.docs.len())).collect::<Vec<_>>(), vec![("2", 2)]);
+        assert_eq!(result.missing, vec!["3".to_string()]);
+        assert_eq!(result.invalid.iter().map(|x| &x.value[..]).collect::<Vec<_>>(), vec!["four"]);
+    }
+
+    #[test]
+    fn test_describe_matches_keeps_order() {
+        let index = test_index();
+        let segment = index.searchable_segment_ids().unwrap()[0];
+        let matches = vec![(segment, 2), (segment, 0)];
+        let described = describe_matches(&["id".to_string()], matches, |field, docs| reconstruct(&index, field, docs)).unwrap();
+        let described = described.iter().map(|x| (x.doc, x.identifying_values[0].trim_right())).collect::<Vec<_>>();
+        assert_eq!(described, vec![(2, "y"), (0, "x")]);
+    }
+}
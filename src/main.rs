@@ -1,6 +1,8 @@
 #![feature(transpose_result)]
 
 extern crate actix_web;
+extern crate bytes;
+extern crate clap;
 extern crate cookie;
 extern crate downcast;
 extern crate env_logger;
@@ -8,7 +10,9 @@ extern crate failure;
 #[macro_use]
 extern crate failure_derive;
 extern crate fst;
+extern crate futures;
 extern crate handlebars;
+extern crate hmac;
 extern crate itertools;
 #[macro_use]
 extern crate log;
@@ -17,19 +21,41 @@ extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
+extern crate sha2;
 extern crate tantivy;
 extern crate url;
 
+mod anonymize;
+mod audit;
+mod check;
+mod compare;
+mod coverage;
 mod debug;
+mod dump;
+mod duplicates;
+mod export;
+mod extract;
+mod fast_fields;
+mod fieldnorms;
 mod fields;
+mod files;
+mod lint;
+mod lookup;
+mod meta;
+mod output;
 mod reconstruct;
+mod search_export;
+mod segments;
 mod space_usage;
+mod space_usage_diff;
 mod top_terms;
+mod uninverted;
+mod vocabulary;
 
 use actix_web::App;
 use failure::Error;
+use failure::err_msg;
 use tantivy::Index;
-use std::env;
 use std::sync::Arc;
 use tantivy::DocId;
 use actix_web::HttpRequest;
@@ -50,8 +76,10 @@ use tantivy::collector::Collector;
 use tantivy::SegmentReader;
 use std::collections::HashSet;
 use itertools::Itertools;
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use tantivy::schema::Schema;
+use tantivy::schema::FieldType;
 use tantivy::query::BooleanQuery;
 use tantivy::query::Occur;
 use tantivy::query::TermQuery;
@@ -73,7 +101,74 @@ use tantivy::SegmentLocalId;
 use tantivy::query::Scorer;
 use tantivy::fastfield::DeleteBitSet;
 use reconstruct::handle_reconstruct;
+use reconstruct::handle_positions;
 use debug::handle_debug;
+use space_usage_diff::handle_space_usage_diff;
+use segments::handle_segments;
+use segments::handle_segment;
+use segments::handle_doc_ordinal;
+use segments::handle_deleted_docs;
+use segments::handle_random_doc;
+use meta::handle_meta;
+use files::handle_files;
+use std::path::PathBuf;
+use clap::Arg;
+use clap::ArgMatches;
+use clap::SubCommand;
+use check::handle_check;
+use check::run_check;
+use dump::run_dump;
+use anonymize::run_anonymize;
+use anonymize::run_anonymize_text;
+use dump::run_import;
+use extract::handle_extract;
+use extract::handle_extract_page;
+use extract::run_extract;
+use std::path::Path;
+use audit::handle_audit;
+use coverage::field_coverage;
+use coverage::FieldCoverage;
+use fieldnorms::handle_fieldnorms;
+use fast_fields::handle_fast_field_stats;
+use vocabulary::handle_vocabulary;
+use lint::handle_lint;
+use duplicates::handle_duplicates;
+use lookup::handle_doc_lookup;
+use lookup::handle_doc_bulk;
+use compare::handle_compare;
+use export::handle_export;
+use search_export::handle_search_export;
+use top_terms::unique_terms;
+use uninverted::handle_cache;
+use output::print_json;
+use output::print_table;
+use output::OutputFormat;
+use space_usage::run_space_usage;
+use debug::run_debug;
+use reconstruct::run_reconstruct;
+use segments::run_segments;
+use files::run_files;
+use meta::run_meta;
+use coverage::run_coverage;
+use fast_fields::run_fast_field_stats;
+use fieldnorms::run_fieldnorms;
+use vocabulary::run_vocabulary;
+use lint::run_lint;
+use duplicates::run_duplicates;
+use audit::run_audit;
+use compare::run_compare;
+use space_usage_diff::run_space_usage_diff;
+use segments::run_segment;
+use segments::run_deleted_docs;
+use lookup::run_lookup;
+use lookup::run_bulk_lookup;
+use reconstruct::run_positions;
+use export::run_export;
+use export::ExportFormat;
+use search_export::run_search_export;
+use search_export::SearchExportColumns;
+use uninverted::EvictionPolicy;
+use uninverted::UninvertedCache;
 
 #[derive(Fail, Debug)]
 enum TantivyViewerError {
@@ -89,6 +184,12 @@ enum TantivyViewerError {
     SegmentNotFoundError,
     #[fail(display="Could not break down unknown query type")]
     UnknownQueryTypeError,
+    #[fail(display="No candidate index to compare against. Start the viewer with --compare")]
+    NoCandidateIndexError,
+    #[fail(display="Doc ordinal is past the last doc of the last segment")]
+    DocOrdinalOutOfRangeError,
+    #[fail(display="The index has no live docs")]
+    NoLiveDocsError,
 }
 
 impl actix_web::error::ResponseError for TantivyViewerError {
@@ -100,7 +201,10 @@ impl actix_web::error::ResponseError for TantivyViewerError {
             | JsonSerializationError
             | UnknownQueryTypeError => http::StatusCode::INTERNAL_SERVER_ERROR,
             QueryParserError(_)
-            | SegmentNotFoundError => http::StatusCode::BAD_REQUEST,
+            | SegmentNotFoundError
+            | NoCandidateIndexError
+            | DocOrdinalOutOfRangeError
+            | NoLiveDocsError => http::StatusCode::BAD_REQUEST,
         };
 
         HttpResponse::Ok()
@@ -132,6 +236,7 @@ struct IndexData {
     segments: Vec<String>,
     num_fields: usize,
     total_usage: usize,
+    has_candidate: bool,
 }
 
 fn handle_index(req: HttpRequest<State>) -> Result<HttpResponse, TantivyViewerError> {
@@ -144,9 +249,10 @@ fn handle_index(req: HttpRequest<State>) -> Result<HttpResponse, TantivyViewerEr
     let num_fields = fields.len();
     let data = IndexData {
         fields,
-        segments: segments.into_iter().map(|x| x.short_uuid_string()).collect(),
+        segments: segments.into_iter().map(|x| x.uuid_string()).collect(),
         num_fields,
         total_usage: space_usage.total(),
+        has_candidate: state.candidate.is_some(),
     };
 
     state.render_template("index", &data)
@@ -157,27 +263,80 @@ struct FieldDetail {
     name: String,
     value_type: String,
     extra_options: String,
+    indexed: bool,
+    numeric_fast: bool,
+    unique_terms: Option<u64>,
+    coverage: Option<FieldCoverage>,
 }
 
-fn handle_field_details(req: HttpRequest<State>) -> Result<HttpResponse, TantivyViewerError> {
-    let state = req.state();
-    let fields = get_fields(&state.index)
+#[derive(Deserialize)]
+struct FieldDetailsQuery {
+    stats: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct FieldDetailsData {
+    stats: bool,
+    fields: Vec<FieldDetail>,
+}
+
+/// With `stats`, also works out each field's coverage and unique term count. These walk
+/// every posting list, fieldnorm and term dictionary of the index, so they are left out
+/// unless asked for.
+fn field_details(index: &Index, stats: bool) -> Result<Vec<FieldDetail>, Error> {
+    let fields = get_fields(index)
         .map_err(TantivyViewerError::TantivyError)?;
+    let mut coverage = if stats { field_coverage(index)? } else { HashMap::new() };
 
     let mut field_details = fields
         .fields
         .into_iter()
-        .map(|(_k, v)| Ok(FieldDetail {
+        .map(|(k, v)| Ok(FieldDetail {
+            unique_terms: if stats && v.indexed { Some(unique_terms(index, &k)?) } else { None },
             name: v.name,
             value_type: format!("{:?}", v.value_type),
-            extra_options: serde_json::to_string(&v.extra_options)?
+            extra_options: serde_json::to_string(&v.extra_options)
+                .map_err(|_e| TantivyViewerError::JsonSerializationError)?,
+            indexed: v.indexed,
+            numeric_fast: v.numeric_fast,
+            coverage: coverage.remove(&k),
         }))
-        .collect::<Result<Vec<_>, std::io::Error>>()
-        .map_err(|_e| TantivyViewerError::JsonSerializationError)?;
+        .collect::<Result<Vec<_>, Error>>()?;
 
     field_details.sort_unstable_by_key(|x| x.name.clone());
+    Ok(field_details)
+}
 
-    state.render_template("field_details", &field_details)
+fn handle_field_details(req: (HttpRequest<State>, Query<FieldDetailsQuery>)) -> Result<HttpResponse, Error> {
+    let (req, params) = req;
+    let state = req.state();
+    let stats = params.stats.unwrap_or(false);
+    let data = FieldDetailsData {
+        stats,
+        fields: field_details(&state.index, stats)?,
+    };
+    Ok(state.render_template("field_details", &data)?)
+}
+
+fn run_fields(index: &Index, stats: bool, format: OutputFormat) -> Result<(), Error> {
+    let field_details = field_details(index, stats)?;
+    match format {
+        OutputFormat::Json => print_json(&field_details)?,
+        OutputFormat::Table => {
+            let rows = field_details.iter()
+                .map(|x| vec![
+                    x.name.clone(),
+                    x.value_type.clone(),
+                    x.indexed.to_string(),
+                    x.numeric_fast.to_string(),
+                    x.unique_terms.map(|x| x.to_string()).unwrap_or_default(),
+                    x.extra_options.clone(),
+                ])
+                .collect::<Vec<_>>();
+            print_table(&["field", "type", "indexed", "fast", "unique terms", "options"], &rows);
+        }
+    }
+    Ok(())
 }
 
 fn handle_space_usage(req: HttpRequest<State>) -> Result<HttpResponse, TantivyViewerError> {
@@ -249,22 +408,39 @@ struct TopTermsData {
     terms: Vec<TermCountData>,
 }
 
-fn handle_top_terms(req: (HttpRequest<State>, Query<TopTermsQuery>)) -> Result<HttpResponse, TantivyViewerError>  {
-    let (req, params) = req;
-    let state = req.state();
-    let field = params.field.clone();
-    let k = params.k.unwrap_or(100);
-    let top_terms = top_terms(&state.index, &field, k).unwrap();
-    let data = TopTermsData {
-        field,
+fn top_terms_data(index: &Index, field: &str, k: usize) -> Result<TopTermsData, Error> {
+    let top_terms = top_terms(index, field, k)?;
+    Ok(TopTermsData {
+        field: field.to_string(),
         terms: top_terms.terms.into_iter().map(|x| TermCountData {
             term: format!("{}", x.term),
             count: x.count
         }).collect()
-    };
+    })
+}
+
+fn handle_top_terms(req: (HttpRequest<State>, Query<TopTermsQuery>)) -> Result<HttpResponse, TantivyViewerError>  {
+    let (req, params) = req;
+    let state = req.state();
+    let k = params.k.unwrap_or(100);
+    let data = top_terms_data(&state.index, &params.field, k).unwrap();
     state.render_template("top_terms", &data)
 }
 
+fn run_top_terms(index: &Index, field: &str, k: usize, format: OutputFormat) -> Result<(), Error> {
+    let data = top_terms_data(index, field, k)?;
+    match format {
+        OutputFormat::Json => print_json(&data)?,
+        OutputFormat::Table => {
+            let rows = data.terms.iter()
+                .map(|x| vec![x.term.clone(), x.count.to_string()])
+                .collect::<Vec<_>>();
+            print_table(&["term", "count"], &rows);
+        }
+    }
+    Ok(())
+}
+
 fn stringify_values(values: Vec<Option<TantivyValue>>) -> String {
     values.into_iter()
         .map(|opt| opt.map(|x| format!("{} ", x)).unwrap_or_default())
@@ -321,12 +497,21 @@ impl Collector for DocCollector {
     }
 }
 
+#[derive(Serialize)]
+struct ExportFieldOption {
+    name: String,
+    reconstructable: bool,
+    stored: bool,
+    identifying: bool,
+}
+
 #[derive(Serialize)]
 struct SearchData {
     query: String,
     reconstructed_fields: Vec<String>,
     docs: Vec<(String, Vec<(DocId, Vec<String>)>)>,
     truncated: bool,
+    export_fields: Vec<ExportFieldOption>,
 }
 
 impl SearchData {
@@ -336,10 +521,36 @@ impl SearchData {
             reconstructed_fields: Vec::new(),
             docs: Vec::new(),
             truncated: false,
+            export_fields: Vec::new(),
         }
     }
 }
 
+fn export_field_options(schema: &Schema, identifying_fields: &[String]) -> Vec<ExportFieldOption> {
+    let mut options = schema.fields()
+        .iter()
+        .map(|field_entry| {
+            let field_type = field_entry.field_type();
+            let reconstructable = match field_type.value_type() {
+                Type::HierarchicalFacet => false,
+                _ => field_type.get_index_record_option().is_some() || match *field_type {
+                    FieldType::U64(ref options) | FieldType::I64(ref options) => options.is_fast(),
+                    FieldType::Bytes => true,
+                    _ => false,
+                },
+            };
+            ExportFieldOption {
+                name: field_entry.name().to_string(),
+                reconstructable,
+                stored: field_entry.is_stored(),
+                identifying: identifying_fields.iter().any(|x| x == field_entry.name()),
+            }
+        })
+        .collect::<Vec<_>>();
+    options.sort_by(|x, y| x.name.cmp(&y.name));
+    options
+}
+
 trait QueryExt {
     fn collect_first_k(&self, searcher: &Searcher, collector: &mut Collector, k: usize) -> tantivy::Result<()>;
 }
@@ -352,7 +563,10 @@ impl<Q: tantivy::query::Query> QueryExt for Q {
         for (segment_ord, segment_reader) in searcher.segment_readers().iter().enumerate() {
             collector.set_segment(segment_ord as SegmentLocalId, segment_reader)?;
             let mut scorer = weight.scorer(segment_reader)?;
-            remaining -= segment_collect_first_k(&mut scorer, collector, segment_reader.delete_bitset(), remaining);
+            remaining = segment_collect_first_k(&mut scorer, collector, segment_reader.delete_bitset(), remaining);
+            if remaining == 0 {
+                break;
+            }
         }
         Ok(())
     }
@@ -366,7 +580,10 @@ trait CollectorExt : Collector + Sized {
         for (segment_ord, segment_reader) in searcher.segment_readers().iter().enumerate() {
             self.set_segment(segment_ord as SegmentLocalId, segment_reader)?;
             let mut scorer = weight.scorer(segment_reader)?;
-            remaining -= segment_collect_first_k(&mut scorer, &mut *self, segment_reader.delete_bitset(), remaining);
+            remaining = segment_collect_first_k(&mut scorer, &mut *self, segment_reader.delete_bitset(), remaining);
+            if remaining == 0 {
+                break;
+            }
         }
         Ok(())
     }
@@ -395,27 +612,21 @@ fn segment_collect_first_k<S: Scorer>(scorer: &mut S, collector: &mut Collector,
     remaining
 }
 
-fn handle_search(req: (HttpRequest<State>, Query<SearchQuery>)) -> Result<HttpResponse, Error> {
-    let (req, params) = req;
-    let state = req.state();
-    let raw_query = match params.query {
-        None => return Ok(state.render_template("search", &SearchData::empty())?),
-        Some(ref query) => query.clone(),
-    };
-
-    let limit = 1000;
+type SearchResults = Vec<(String, Vec<(DocId, Vec<String>)>)>;
 
-    let query_parser = QueryParser::for_index(&state.index, vec![]);
-    let query = query_parser.parse_query(&raw_query).map_err(TantivyViewerError::QueryParserError)?;
+/// Runs `raw_query` and reconstructs `fields` for up to `limit` of the matching docs,
+/// grouped by segment. Also returns whether more docs matched than were kept.
+fn search<R>(index: &Index, raw_query: &str, fields: &[String], limit: usize, reconstruct_field: R) -> Result<(SearchResults, bool), Error>
+    where R: Fn(&str, &HashMap<SegmentId, Vec<DocId>>) -> Result<HashMap<SegmentId, Vec<(DocId, Vec<Option<TantivyValue>>)>>, Error> {
+    let query_parser = QueryParser::for_index(index, vec![]);
+    let query = query_parser.parse_query(raw_query).map_err(TantivyViewerError::QueryParserError)?;
 
-    let searcher = state.index.searcher();
+    let searcher = index.searcher();
     let mut collector = DocCollector::new();
     collector.collect_first_k(&*searcher, &*query, limit + 1).map_err(TantivyViewerError::TantivyError)?;
 
     let docs = collector.into_docs();
 
-    let identifying_fields = get_identifying_fields(&req);
-
     let mut remaining = limit;
     let mut docs_to_reconstruct = HashMap::new();
     let mut truncated = false;
@@ -436,8 +647,8 @@ fn handle_search(req: (HttpRequest<State>, Query<SearchQuery>)) -> Result<HttpRe
     }
 
     let mut reconstructed_fields = Vec::new();
-    for field in identifying_fields.iter() {
-        let reconstructed = reconstruct(&state.index, &*field, &docs_to_reconstruct)?;
+    for field in fields.iter() {
+        let reconstructed = reconstruct_field(&*field, &docs_to_reconstruct)?;
         let reconstructed = reconstructed
             .into_iter()
             .map(|(segment, docs)| {
@@ -459,22 +670,91 @@ fn handle_search(req: (HttpRequest<State>, Query<SearchQuery>)) -> Result<HttpRe
             }
             segment_docs.push((doc, doc_reconstructed_fields));
         }
-        result.push((segment.short_uuid_string(), segment_docs));
+        result.push((segment.uuid_string(), segment_docs));
     }
 
+    Ok((result, truncated))
+}
+
+fn handle_search(req: (HttpRequest<State>, Query<SearchQuery>)) -> Result<HttpResponse, Error> {
+    let (req, params) = req;
+    let state = req.state();
+    let raw_query = match params.query {
+        None => return Ok(state.render_template("search", &SearchData::empty())?),
+        Some(ref query) => query.clone(),
+    };
+
+    let limit = 1000;
+    let identifying_fields = get_identifying_fields(&req);
+    let (docs, truncated) = search(&state.index, &raw_query, &identifying_fields, limit, |field, docs| state.reconstruct(field, docs))?;
 
     let data = SearchData {
         query: raw_query,
+        export_fields: export_field_options(&state.index.schema(), &identifying_fields),
         reconstructed_fields: identifying_fields,
-        docs: result,
+        docs,
         truncated,
     };
 
     Ok(state.render_template("search", &data)?)
 }
 
+#[derive(Serialize)]
+struct SearchResultRow {
+    segment: String,
+    doc: DocId,
+    fields: BTreeMap<String, String>,
+}
+
+#[derive(Serialize)]
+struct SearchResultData {
+    query: String,
+    docs: Vec<SearchResultRow>,
+    truncated: bool,
+}
+
+fn run_search(index: &Index, raw_query: &str, fields: Vec<String>, limit: usize, format: OutputFormat) -> Result<(), Error> {
+    let (docs, truncated) = search(index, raw_query, &fields, limit, |field, docs| reconstruct(index, field, docs))?;
+    match format {
+        OutputFormat::Json => {
+            let docs = docs.into_iter()
+                .flat_map(|(segment, docs)| {
+                    let fields = &fields;
+                    docs.into_iter().map(move |(doc, values)| SearchResultRow {
+                        segment: segment.clone(),
+                        doc,
+                        fields: fields.iter().cloned().zip(values.into_iter().map(|x| x.trim_right().to_string())).collect(),
+                    })
+                })
+                .collect();
+            print_json(&SearchResultData { query: raw_query.to_string(), docs, truncated })?;
+        }
+        OutputFormat::Table => {
+            let mut headers = vec!["segment", "doc"];
+            headers.extend(fields.iter().map(|x| &x[..]));
+            let rows = docs.into_iter()
+                .flat_map(|(segment, docs)| docs.into_iter().map(move |(doc, values)| {
+                    let mut row = vec![segment.clone(), doc.to_string()];
+                    row.extend(values.into_iter().map(|x| x.trim_right().to_string()));
+                    row
+                }))
+                .collect::<Vec<_>>();
+            print_table(&headers, &rows);
+            if truncated {
+                println!("[...Truncated after {} docs...]", limit);
+            }
+        }
+    }
+    Ok(())
+}
+
 struct State {
     index: Arc<Index>,
+    index_path: PathBuf,
+    candidate: Option<Arc<Index>>,
+    uninverted: Option<Arc<UninvertedCache>>,
+    /// Where the `/extract` page may write new indexes. The page is disabled without it.
+    extract_dir: Option<PathBuf>,
     handlebars: Arc<Handlebars>,
 }
 
@@ -482,6 +762,10 @@ impl Clone for State {
     fn clone(&self) -> Self {
         State {
             index: self.index.clone(),
+            index_path: self.index_path.clone(),
+            candidate: self.candidate.clone(),
+            uninverted: self.uninverted.clone(),
+            extract_dir: self.extract_dir.clone(),
             handlebars: self.handlebars.clone(),
         }
     }
@@ -495,6 +779,14 @@ impl State {
             .body(self.handlebars.render(name, &data).map_err(TantivyViewerError::RenderingError)?)
         )
     }
+
+    /// Reconstructs a field through the uninverted cache when it is enabled.
+    fn reconstruct(&self, field: &str, docs: &HashMap<SegmentId, Vec<DocId>>) -> Result<HashMap<SegmentId, Vec<(DocId, Vec<Option<TantivyValue>>)>>, Error> {
+        match self.uninverted {
+            Some(ref cache) => cache.reconstruct(&self.index, field, docs),
+            None => reconstruct(&self.index, field, docs),
+        }
+    }
 }
 
 fn pretty_bytes(h: &Helper, _: &Handlebars, rc: &mut RenderContext) -> Result<(), RenderError> {
@@ -508,6 +800,26 @@ fn pretty_bytes(h: &Helper, _: &Handlebars, rc: &mut RenderContext) -> Result<()
     Ok(())
 }
 
+fn short_id(h: &Helper, _: &Handlebars, rc: &mut RenderContext) -> Result<(), RenderError> {
+    if let Some(param) = h.param(0) {
+        if let Some(param) = param.value().as_str() {
+            rc.writer.write(param.chars().take(8).collect::<String>().as_bytes())?;
+            return Ok(());
+        }
+    }
+    Err(RenderError::new("Invalid argument to short_id. Expected string."))
+}
+
+/// Writes a number, or "-" when it is missing, so that a legitimate 0 still shows up.
+fn or_dash(h: &Helper, _: &Handlebars, rc: &mut RenderContext) -> Result<(), RenderError> {
+    match h.param(0).map(|x| x.value()) {
+        None | Some(&serde_json::Value::Null) => rc.writer.write("-".as_bytes())?,
+        Some(value) if value.is_number() => rc.writer.write(value.to_string().as_bytes())?,
+        Some(_) => return Err(RenderError::new("Invalid argument to or_dash. Expected number or null.")),
+    };
+    Ok(())
+}
+
 fn url_encode(h: &Helper, _: &Handlebars, rc: &mut RenderContext) -> Result<(), RenderError> {
     if let Some(param) = h.param(0) {
         if let Some(param) = param.value().as_str() {
@@ -638,15 +950,517 @@ fn push_query_to_string(query: &tantivy::query::Query, schema: &Schema, output:
     }
 }
 
+fn format_arg() -> Arg<'static, 'static> {
+    Arg::with_name("format")
+        .long("format")
+        .value_name("FORMAT")
+        .help("Print a table, or JSON for scripts")
+        .possible_values(&["table", "json"])
+        .default_value("table")
+}
+
+fn output_format(matches: &ArgMatches) -> Result<OutputFormat, Error> {
+    OutputFormat::parse(matches.value_of("format"))
+}
+
+fn export_format_arg() -> Arg<'static, 'static> {
+    Arg::with_name("format")
+        .long("format")
+        .value_name("FORMAT")
+        .possible_values(&["ndjson", "csv"])
+        .default_value("ndjson")
+}
+
+fn string_values(matches: &ArgMatches, name: &str) -> Vec<String> {
+    matches.values_of(name).map(|x| x.map(|x| x.to_string()).collect()).unwrap_or_default()
+}
+
+fn key_arg() -> Arg<'static, 'static> {
+    Arg::with_name("key")
+        .long("key")
+        .value_name("KEY")
+        .env("TANTIVY_VIEWER_ANONYMIZE_KEY")
+        .hide_env_values(true)
+        .help("Secret the hashes are keyed with. The same key always gives the same hashes. \
+               Prefer the environment variable or --key-file, which keep it out of the process list")
+}
+
+fn key_file_arg() -> Arg<'static, 'static> {
+    Arg::with_name("key_file")
+        .long("key-file")
+        .value_name("PATH")
+        .help("Read the secret from this file, ignoring a trailing newline. Takes precedence over --key")
+}
+
+fn anonymize_key(matches: &ArgMatches) -> Result<String, Error> {
+    let key = match matches.value_of("key_file") {
+        Some(path) => fs::read_to_string(path)?.trim_right_matches(|c| c == '\n' || c == '\r').to_string(),
+        None => matches.value_of("key")
+            .map(|x| x.to_string())
+            .ok_or(err_msg("Pass the key with --key-file, TANTIVY_VIEWER_ANONYMIZE_KEY or --key"))?,
+    };
+    // With an empty key nothing is secret, so anyone could hash a dictionary and reverse the terms.
+    if key.is_empty() {
+        return Err(err_msg("The anonymization key is empty"));
+    }
+    Ok(key)
+}
+
 fn main() -> Result<(), Error> {
     env_logger::init();
 
-    let args = env::args().collect::<Vec<_>>();
-    let index = Arc::new(Index::open_in_dir(&args[1]).unwrap());
+    let matches = clap::App::new("tantivy-viewer")
+        .about("Explore and diagnose a tantivy index")
+        .arg(Arg::with_name("index")
+            .help("Path to the tantivy index directory")
+            .required(true))
+        .arg(Arg::with_name("compare")
+            .long("compare")
+            .value_name("CANDIDATE")
+            .takes_value(true)
+            .help("Path to a second index to compare against the first"))
+        .arg(Arg::with_name("uninverted_cache_mb")
+            .long("uninverted-cache-mb")
+            .value_name("MB")
+            .takes_value(true)
+            .help("Keep uninverted identifying fields in memory, up to this many megabytes"))
+        .arg(Arg::with_name("uninverted_cache_policy")
+            .long("uninverted-cache-policy")
+            .value_name("POLICY")
+            .possible_values(&["lru", "lfu", "fifo"])
+            .default_value("lru")
+            .help("Which cached fields to evict first when the cache is full"))
+        .arg(Arg::with_name("extract_dir")
+            .long("extract-dir")
+            .value_name("DIR")
+            .takes_value(true)
+            .help("Let the /extract page write new indexes under this directory. The page is disabled without it"))
+        .subcommand(SubCommand::with_name("space-usage")
+            .about("Print the space used by each index component and field")
+            .arg(format_arg()))
+        .subcommand(SubCommand::with_name("fields")
+            .about("Print the fields of the schema with their options")
+            .arg(Arg::with_name("stats")
+                .long("stats")
+                .help("Also count unique terms and coverage, which reads every field"))
+            .arg(format_arg()))
+        .subcommand(SubCommand::with_name("segments")
+            .about("Print the searchable segments with their doc counts and sizes")
+            .arg(format_arg()))
+        .subcommand(SubCommand::with_name("segment")
+            .about("Print the doc counts of a segment and the space each field uses in it")
+            .arg(Arg::with_name("segment")
+                .help("Segment id, or a unique prefix of it")
+                .required(true))
+            .arg(format_arg()))
+        .subcommand(SubCommand::with_name("deleted-docs")
+            .about("Print the docs marked as deleted in a segment, with reconstructed fields")
+            .arg(Arg::with_name("segment")
+                .help("Segment id, or a unique prefix of it")
+                .required(true))
+            .arg(Arg::with_name("field")
+                .long("field")
+                .value_name("FIELD")
+                .help("Reconstruct this field for each doc. May be repeated")
+                .multiple(true)
+                .number_of_values(1))
+            .arg(Arg::with_name("limit")
+                .long("limit")
+                .value_name("N")
+                .default_value("1000"))
+            .arg(format_arg()))
+        .subcommand(SubCommand::with_name("files")
+            .about("Print the files of the index directory and whether a searchable segment references them")
+            .arg(format_arg()))
+        .subcommand(SubCommand::with_name("meta")
+            .about("Print meta.json and the files it or .managed.json disagree with the directory about")
+            .arg(format_arg()))
+        .subcommand(SubCommand::with_name("space-usage-diff")
+            .about("Print how the space used by the index given with --compare differs from this one")
+            .arg(format_arg()))
+        .subcommand(SubCommand::with_name("coverage")
+            .about("Print how many live docs have a value for each field")
+            .arg(format_arg()))
+        .subcommand(SubCommand::with_name("fast-field-stats")
+            .about("Print the distribution of a U64 or I64 fast field")
+            .arg(Arg::with_name("field")
+                .required(true))
+            .arg(format_arg()))
+        .subcommand(SubCommand::with_name("fieldnorms")
+            .about("Print the distribution of doc lengths of an indexed field")
+            .arg(Arg::with_name("field")
+                .required(true))
+            .arg(Arg::with_name("k")
+                .short("k")
+                .value_name("K")
+                .help("Number of longest and shortest docs to print")
+                .default_value("20"))
+            .arg(format_arg()))
+        .subcommand(SubCommand::with_name("vocabulary")
+            .about("Print term dictionary statistics of an indexed field")
+            .arg(Arg::with_name("field")
+                .required(true))
+            .arg(format_arg()))
+        .subcommand(SubCommand::with_name("lint")
+            .about("Print schema options that don't suit the data, with estimated savings")
+            .arg(format_arg()))
+        .subcommand(SubCommand::with_name("duplicates")
+            .about("Print terms of an indexed field shared by more than one live doc")
+            .arg(Arg::with_name("field")
+                .required(true))
+            .arg(Arg::with_name("limit")
+                .long("limit")
+                .value_name("N")
+                .default_value("1000"))
+            .arg(format_arg()))
+        .subcommand(SubCommand::with_name("audit")
+            .about("Compare stored fields with what the inverted index and fast fields hold")
+            .arg(Arg::with_name("sample")
+                .long("sample")
+                .value_name("N")
+                .help("Docs checked per segment")
+                .default_value("1000"))
+            .arg(format_arg()))
+        .subcommand(SubCommand::with_name("compare")
+            .about("Print the reconstructed fields of two docs side by side")
+            .arg(Arg::with_name("segment_a")
+                .help("Segment id of the first doc, or a unique prefix of it")
+                .required(true))
+            .arg(Arg::with_name("doc_a")
+                .required(true))
+            .arg(Arg::with_name("segment_b")
+                .help("Segment id of the second doc, or a unique prefix of it")
+                .required(true))
+            .arg(Arg::with_name("doc_b")
+                .required(true))
+            .arg(format_arg()))
+        .subcommand(SubCommand::with_name("top-terms")
+            .about("Print the most common terms of a field")
+            .arg(Arg::with_name("field")
+                .required(true))
+            .arg(Arg::with_name("k")
+                .short("k")
+                .value_name("K")
+                .help("Number of terms to print")
+                .default_value("100"))
+            .arg(format_arg()))
+        .subcommand(SubCommand::with_name("reconstruct")
+            .about("Print the reconstructed fields of a doc")
+            .arg(Arg::with_name("segment")
+                .help("Segment id, or a unique prefix of it")
+                .required(true))
+            .arg(Arg::with_name("doc")
+                .required(true))
+            .arg(Arg::with_name("field")
+                .long("field")
+                .value_name("FIELD")
+                .help("Only reconstruct this field"))
+            .arg(format_arg()))
+        .subcommand(SubCommand::with_name("positions")
+            .about("Print the token at each position of a text field of a doc")
+            .arg(Arg::with_name("segment")
+                .help("Segment id, or a unique prefix of it")
+                .required(true))
+            .arg(Arg::with_name("doc")
+                .required(true))
+            .arg(Arg::with_name("field")
+                .required(true))
+            .arg(format_arg()))
+        .subcommand(SubCommand::with_name("lookup")
+            .about("Print the live docs holding a value in an indexed field")
+            .arg(Arg::with_name("field")
+                .required(true))
+            .arg(Arg::with_name("value")
+                .required(true))
+            .arg(Arg::with_name("identifying_field")
+                .long("identifying-field")
+                .value_name("FIELD")
+                .help("Reconstruct this field for each doc. May be repeated")
+                .multiple(true)
+                .number_of_values(1))
+            .arg(format_arg()))
+        .subcommand(SubCommand::with_name("lookup-bulk")
+            .about("Print which of many values of an indexed field match one, none or several live docs")
+            .arg(Arg::with_name("field")
+                .required(true))
+            .arg(Arg::with_name("values")
+                .help("File with one value per line. Read from stdin if omitted"))
+            .arg(format_arg()))
+        .subcommand(SubCommand::with_name("export")
+            .about("Write one field of every live doc, or of those matching a query, to stdout")
+            .arg(Arg::with_name("field")
+                .required(true))
+            .arg(Arg::with_name("query")
+                .long("query")
+                .value_name("QUERY")
+                .help("Only export docs matching this query"))
+            .arg(export_format_arg()))
+        .subcommand(SubCommand::with_name("search-export")
+            .about("Write the docs matching a query to stdout, with the chosen columns")
+            .arg(Arg::with_name("query")
+                .required(true))
+            .arg(Arg::with_name("address")
+                .long("address")
+                .help("Include the segment and doc id of each doc"))
+            .arg(Arg::with_name("score")
+                .long("score")
+                .help("Include the score of each doc"))
+            .arg(Arg::with_name("reconstructed")
+                .long("reconstructed")
+                .value_name("FIELD")
+                .help("Include this field rebuilt from the index. May be repeated")
+                .multiple(true)
+                .number_of_values(1))
+            .arg(Arg::with_name("stored")
+                .long("stored")
+                .value_name("FIELD")
+                .help("Include this field from the doc store. May be repeated")
+                .multiple(true)
+                .number_of_values(1))
+            .arg(export_format_arg()))
+        .subcommand(SubCommand::with_name("search")
+            .about("Print the docs matching a query, with reconstructed fields")
+            .arg(Arg::with_name("query")
+                .required(true))
+            .arg(Arg::with_name("field")
+                .long("field")
+                .value_name("FIELD")
+                .help("Reconstruct this field for each doc. May be repeated")
+                .multiple(true)
+                .number_of_values(1))
+            .arg(Arg::with_name("limit")
+                .long("limit")
+                .value_name("N")
+                .default_value("1000"))
+            .arg(format_arg()))
+        .subcommand(SubCommand::with_name("debug")
+            .about("Print the number of docs matched by each part of a query")
+            .arg(Arg::with_name("query")
+                .required(true))
+            .arg(Arg::with_name("salient_docs_query")
+                .long("salient-docs-query")
+                .value_name("QUERY")
+                .help("Only count docs also matching this query"))
+            .arg(format_arg()))
+        .subcommand(SubCommand::with_name("check")
+            .about("Verify the integrity of every segment instead of starting the server")
+            .arg(format_arg()))
+        .subcommand(SubCommand::with_name("dump")
+            .about("Write every live doc as NDJSON, with the schema alongside")
+            .arg(Arg::with_name("output")
+                .help("Directory to write documents.ndjson and schema.json into")
+                .required(true)))
+        .subcommand(SubCommand::with_name("anonymize")
+            .about("Write a copy of the index with text, bytes and facet values replaced by keyed hashes")
+            .arg(key_arg())
+            .arg(key_file_arg())
+            .arg(Arg::with_name("keep_top")
+                .long("keep-top")
+                .value_name("N")
+                .help("Keep the N most common terms of each text field in clear text")
+                .default_value("0"))
+            .arg(Arg::with_name("output")
+                .help("Directory of the new index. Must not exist yet, or be empty")
+                .required(true)))
+        .subcommand(SubCommand::with_name("anonymize-text")
+            .about("Print text as the anonymize subcommand would write it, for rewriting queries")
+            .arg(key_arg())
+            .arg(key_file_arg())
+            .arg(Arg::with_name("keep_top")
+                .long("keep-top")
+                .value_name("N")
+                .default_value("0"))
+            .arg(Arg::with_name("field")
+                .help("Text field the text belongs to")
+                .required(true))
+            .arg(Arg::with_name("text")
+                .required(true)))
+        .subcommand(SubCommand::with_name("extract")
+            .about("Write the live docs matching a query into a new index with the same schema")
+            .arg(Arg::with_name("query")
+                .help("Query selecting the docs to extract")
+                .required(true))
+            .arg(Arg::with_name("output")
+                .help("Directory of the new index. Must not exist yet, or be empty")
+                .required(true)))
+        .subcommand(SubCommand::with_name("import")
+            .about("Build a new index at the index path from a dump")
+            .arg(Arg::with_name("dump")
+                .help("Directory written by the dump subcommand")
+                .required(true)))
+        .get_matches();
+
+    let index_path = PathBuf::from(matches.value_of("index").unwrap());
+    if let ("import", Some(import_matches)) = matches.subcommand() {
+        return run_import(Path::new(import_matches.value_of("dump").unwrap()), &index_path);
+    }
+    let index = Arc::new(Index::open_in_dir(&index_path).map_err(TantivyViewerError::TantivyError)?);
+    let candidate = match matches.value_of("compare") {
+        Some(path) => Some(Arc::new(Index::open_in_dir(path).map_err(TantivyViewerError::TantivyError)?)),
+        None => None,
+    };
+
+    let uninverted = match matches.value_of("uninverted_cache_mb") {
+        Some(mb) => Some(Arc::new(UninvertedCache::new(
+            mb.parse::<usize>()? * 1024 * 1024,
+            EvictionPolicy::parse(matches.value_of("uninverted_cache_policy"))?,
+        ))),
+        None => None,
+    };
+
+    match matches.subcommand() {
+        ("space-usage", Some(report_matches)) => return run_space_usage(&index, output_format(report_matches)?),
+        ("fields", Some(report_matches)) => return run_fields(
+            &index,
+            report_matches.is_present("stats"),
+            output_format(report_matches)?,
+        ),
+        ("segments", Some(report_matches)) => return run_segments(&index, output_format(report_matches)?),
+        ("segment", Some(report_matches)) => return run_segment(
+            &index,
+            report_matches.value_of("segment").unwrap(),
+            output_format(report_matches)?,
+        ),
+        ("deleted-docs", Some(report_matches)) => return run_deleted_docs(
+            &index,
+            report_matches.value_of("segment").unwrap(),
+            string_values(report_matches, "field"),
+            report_matches.value_of("limit").unwrap().parse()?,
+            output_format(report_matches)?,
+        ),
+        ("files", Some(report_matches)) => return run_files(&index, &index_path, output_format(report_matches)?),
+        ("meta", Some(report_matches)) => return run_meta(&index_path, output_format(report_matches)?),
+        ("space-usage-diff", Some(report_matches)) => return run_space_usage_diff(
+            &index,
+            candidate.as_ref().map(|x| &**x),
+            output_format(report_matches)?,
+        ),
+        ("coverage", Some(report_matches)) => return run_coverage(&index, output_format(report_matches)?),
+        ("fast-field-stats", Some(report_matches)) => return run_fast_field_stats(
+            &index,
+            report_matches.value_of("field").unwrap(),
+            output_format(report_matches)?,
+        ),
+        ("fieldnorms", Some(report_matches)) => return run_fieldnorms(
+            &index,
+            report_matches.value_of("field").unwrap(),
+            report_matches.value_of("k").unwrap().parse()?,
+            output_format(report_matches)?,
+        ),
+        ("vocabulary", Some(report_matches)) => return run_vocabulary(
+            &index,
+            report_matches.value_of("field").unwrap(),
+            output_format(report_matches)?,
+        ),
+        ("lint", Some(report_matches)) => return run_lint(&index, output_format(report_matches)?),
+        ("duplicates", Some(report_matches)) => return run_duplicates(
+            &index,
+            report_matches.value_of("field").unwrap(),
+            report_matches.value_of("limit").unwrap().parse()?,
+            output_format(report_matches)?,
+        ),
+        ("audit", Some(report_matches)) => return run_audit(
+            &index,
+            report_matches.value_of("sample").unwrap().parse()?,
+            output_format(report_matches)?,
+        ),
+        ("compare", Some(report_matches)) => return run_compare(
+            &index,
+            (report_matches.value_of("segment_a").unwrap(), report_matches.value_of("doc_a").unwrap().parse()?),
+            (report_matches.value_of("segment_b").unwrap(), report_matches.value_of("doc_b").unwrap().parse()?),
+            output_format(report_matches)?,
+        ),
+        ("top-terms", Some(report_matches)) => return run_top_terms(
+            &index,
+            report_matches.value_of("field").unwrap(),
+            report_matches.value_of("k").unwrap().parse()?,
+            output_format(report_matches)?,
+        ),
+        ("reconstruct", Some(report_matches)) => return run_reconstruct(
+            &index,
+            report_matches.value_of("segment").unwrap(),
+            report_matches.value_of("doc").unwrap().parse()?,
+            report_matches.value_of("field"),
+            output_format(report_matches)?,
+        ),
+        ("positions", Some(report_matches)) => return run_positions(
+            &index,
+            report_matches.value_of("segment").unwrap(),
+            report_matches.value_of("doc").unwrap().parse()?,
+            report_matches.value_of("field").unwrap(),
+            output_format(report_matches)?,
+        ),
+        ("lookup", Some(report_matches)) => return run_lookup(
+            &index,
+            report_matches.value_of("field").unwrap(),
+            report_matches.value_of("value").unwrap(),
+            string_values(report_matches, "identifying_field"),
+            output_format(report_matches)?,
+        ),
+        ("lookup-bulk", Some(report_matches)) => return run_bulk_lookup(
+            &index,
+            report_matches.value_of("field").unwrap(),
+            report_matches.value_of("values").map(Path::new),
+            output_format(report_matches)?,
+        ),
+        ("export", Some(export_matches)) => return run_export(
+            &index,
+            export_matches.value_of("field").unwrap(),
+            export_matches.value_of("query"),
+            ExportFormat::parse(export_matches.value_of("format"))?,
+        ),
+        ("search-export", Some(export_matches)) => return run_search_export(
+            &index,
+            export_matches.value_of("query").unwrap(),
+            SearchExportColumns {
+                address: export_matches.is_present("address"),
+                score: export_matches.is_present("score"),
+                reconstructed: string_values(export_matches, "reconstructed"),
+                stored: string_values(export_matches, "stored"),
+            },
+            ExportFormat::parse(export_matches.value_of("format"))?,
+        ),
+        ("search", Some(report_matches)) => return run_search(
+            &index,
+            report_matches.value_of("query").unwrap(),
+            string_values(report_matches, "field"),
+            report_matches.value_of("limit").unwrap().parse()?,
+            output_format(report_matches)?,
+        ),
+        ("debug", Some(report_matches)) => return run_debug(
+            &index,
+            report_matches.value_of("query").unwrap(),
+            report_matches.value_of("salient_docs_query"),
+            output_format(report_matches)?,
+        ),
+        ("check", Some(report_matches)) => return run_check(&index, output_format(report_matches)?),
+        ("extract", Some(extract_matches)) => return run_extract(
+            &index,
+            extract_matches.value_of("query").unwrap(),
+            Path::new(extract_matches.value_of("output").unwrap()),
+        ),
+        ("anonymize", Some(anonymize_matches)) => return run_anonymize(
+            &index,
+            &anonymize_key(anonymize_matches)?,
+            anonymize_matches.value_of("keep_top").unwrap().parse()?,
+            Path::new(anonymize_matches.value_of("output").unwrap()),
+        ),
+        ("anonymize-text", Some(text_matches)) => return run_anonymize_text(
+            &index,
+            &anonymize_key(text_matches)?,
+            text_matches.value_of("keep_top").unwrap().parse()?,
+            text_matches.value_of("field").unwrap(),
+            text_matches.value_of("text").unwrap(),
+        ),
+        ("dump", Some(dump_matches)) => return run_dump(&index, Path::new(dump_matches.value_of("output").unwrap())),
+        _ => {},
+    }
 
     let mut handlebars = Handlebars::new();
     handlebars.register_helper("pretty_bytes", Box::new(pretty_bytes));
     handlebars.register_helper("url_encode", Box::new(url_encode));
+    handlebars.register_helper("short_id", Box::new(short_id));
+    handlebars.register_helper("or_dash", Box::new(or_dash));
     for entry in fs::read_dir("./templates")? {
         let entry = entry?;
         let filename = entry.file_name();
@@ -660,19 +1474,138 @@ fn main() -> Result<(), Error> {
 
     let state = State {
         index: index.clone(),
+        index_path,
+        candidate,
+        uninverted,
+        extract_dir: matches.value_of("extract_dir").map(PathBuf::from),
         handlebars: Arc::new(handlebars),
     };
     server::new(move ||
         App::with_state(state.clone())
             .resource("/", |r| r.f(handle_index))
-            .resource("/field_details", |r| r.f(handle_field_details))
+            .resource("/field_details", |r| r.method(http::Method::GET).with(handle_field_details))
             .resource("/space_usage", |r| r.f(handle_space_usage))
+            .resource("/space_usage_diff", |r| r.f(handle_space_usage_diff))
             .resource("/configure", |r| r.f(handle_configure))
+            .resource("/segments", |r| r.f(handle_segments))
+            .resource("/meta", |r| r.f(handle_meta))
+            .resource("/files", |r| r.f(handle_files))
+            .resource("/check", |r| r.f(handle_check))
+            .resource("/cache", |r| r.f(handle_cache))
+            .resource("/audit", |r| r.method(http::Method::GET).with(handle_audit))
+            .resource("/lint", |r| r.f(handle_lint))
+            .resource("/duplicates", |r| r.method(http::Method::GET).with(handle_duplicates))
+            .resource("/deleted_docs", |r| r.method(http::Method::GET).with(handle_deleted_docs))
+            .resource("/segment", |r| r.method(http::Method::GET).with(handle_segment))
             .resource("/top_terms", |r| r.method(http::Method::GET).with(handle_top_terms))
+            .resource("/fieldnorms", |r| r.method(http::Method::GET).with(handle_fieldnorms))
+            .resource("/fast_field_stats", |r| r.method(http::Method::GET).with(handle_fast_field_stats))
+            .resource("/vocabulary", |r| r.method(http::Method::GET).with(handle_vocabulary))
             .resource("/reconstruct", |r| r.method(http::Method::GET).with(handle_reconstruct))
+            .resource("/export", |r| r.method(http::Method::GET).with(handle_export))
+            .resource("/positions", |r| r.method(http::Method::GET).with(handle_positions))
+            .resource("/doc", |r| r.method(http::Method::GET).with(handle_doc_lookup))
+            .resource("/doc_bulk", |r| r.method(http::Method::GET).with(handle_doc_bulk))
+            .resource("/doc/{ordinal}", |r| r.method(http::Method::GET).with(handle_doc_ordinal))
+            .resource("/random_doc", |r| r.f(handle_random_doc))
+            .resource("/compare", |r| r.method(http::Method::GET).with(handle_compare))
             .resource("/search", |r| r.method(http::Method::GET).with(handle_search))
+            .resource("/extract", |r| {
+                r.method(http::Method::GET).with(handle_extract_page);
+                r.method(http::Method::POST).with(handle_extract);
+            })
+            .resource("/search_export", |r| r.f(handle_search_export))
             .resource("/debug", |r| r.method(http::Method::GET).with(handle_debug))
     ).bind("0.0.0.0:3000").unwrap().run();
 
     Ok(())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tantivy::Document;
+    use tantivy::Score;
+    use tantivy::merge_policy::NoMergePolicy;
+    use tantivy::query::Query;
+    use tantivy::schema::SchemaBuilder;
+    use tantivy::schema::STRING;
+
+    #[derive(Default)]
+    struct AddressCollector {
+        segment: SegmentLocalId,
+        docs: Vec<(SegmentLocalId, DocId)>,
+    }
+
+    impl Collector for AddressCollector {
+        fn set_segment(&mut self, segment_local_id: SegmentLocalId, _segment: &SegmentReader) -> tantivy::Result<()> {
+            self.segment = segment_local_id;
+            Ok(())
+        }
+
+        fn collect(&mut self, doc: DocId, _score: Score) {
+            self.docs.push((self.segment, doc));
+        }
+
+        fn requires_scoring(&self) -> bool {
+            false
+        }
+    }
+
+    /// Three segments of two docs each, with the first doc of the second segment deleted.
+    fn test_index() -> Index {
+        let mut schema_builder = SchemaBuilder::default();
+        let id = schema_builder.add_text_field("id", STRING);
+        let index = Index::create_in_ram(schema_builder.build());
+        let mut writer = index.writer_with_num_threads(1, 40_000_000).unwrap();
+        writer.set_merge_policy(Box::new(NoMergePolicy));
+        for segment in 0..3 {
+            for doc in 0..2 {
+                let mut document = Document::default();
+                document.add_text(id, &format!("{}-{}", segment, doc));
+                writer.add_document(document);
+            }
+            writer.commit().unwrap();
+        }
+        writer.delete_term(Term::from_field_text(id, "1-0"));
+        writer.commit().unwrap();
+        index.load_searchers().unwrap();
+        index
+    }
+
+    #[test]
+    fn test_segment_collect_first_k_skips_deleted_docs() {
+        let index = test_index();
+        let searcher = index.searcher();
+        let weight = AllQuery.weight(&*searcher, false).unwrap();
+        let segment = searcher.segment_readers().iter().find(|x| x.num_deleted_docs() == 1).unwrap();
+
+        let mut collector = AddressCollector::default();
+        let mut scorer = weight.scorer(segment).unwrap();
+        assert_eq!(segment_collect_first_k(&mut scorer, &mut collector, segment.delete_bitset(), 5), 4);
+        assert_eq!(collector.docs.iter().map(|x| x.1).collect::<Vec<_>>(), vec![1]);
+
+        let mut collector = AddressCollector::default();
+        let mut scorer = weight.scorer(segment).unwrap();
+        assert_eq!(segment_collect_first_k(&mut scorer, &mut collector, None, 1), 0);
+        assert_eq!(collector.docs.len(), 1);
+    }
+
+    #[test]
+    fn test_collect_first_k_spans_segments() {
+        let index = test_index();
+        let searcher = index.searcher();
+        for &(k, expected) in &[(2, 2), (4, 4), (10, 5)] {
+            let mut collector = AddressCollector::default();
+            collector.collect_first_k(&*searcher, &AllQuery, k).unwrap();
+            assert_eq!(collector.docs.len(), expected);
+
+            let mut collector = AddressCollector::default();
+            AllQuery.collect_first_k(&*searcher, &mut collector, k).unwrap();
+            assert_eq!(collector.docs.len(), expected);
+        }
+        let mut collector = AddressCollector::default();
+        collector.collect_first_k(&*searcher, &AllQuery, 10).unwrap();
+        let segments = collector.docs.iter().map(|x| x.0).collect::<HashSet<_>>();
+        assert_eq!(segments.len(), 3);
+    }
+}
@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use failure::Error;
+use serde_json;
+use serde_json::Value;
+
+use files::list_index_files;
+use files::IndexFile;
+use output::print_json;
+use output::print_table;
+use output::OutputFormat;
+use State;
+
+const META_FILENAME: &str = "meta.json";
+const MANAGED_FILENAME: &str = ".managed.json";
+
+#[derive(Deserialize)]
+struct RawDeleteMeta {
+    num_deleted_docs: u32,
+    opstamp: u64,
+}
+
+#[derive(Deserialize)]
+struct RawSegmentMeta {
+    segment_id: String,
+    max_doc: u32,
+    deletes: Option<RawDeleteMeta>,
+}
+
+#[derive(Deserialize)]
+struct RawIndexMeta {
+    segments: Vec<RawSegmentMeta>,
+    schema: Value,
+    opstamp: u64,
+    #[serde(default)]
+    payload: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct MetaSegment {
+    id: String,
+    max_doc: u32,
+    num_deleted_docs: Option<u32>,
+    delete_opstamp: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct ManagedFile {
+    name: String,
+    on_disk: bool,
+}
+
+#[derive(Serialize)]
+pub struct MetaData {
+    opstamp: u64,
+    payload: Option<String>,
+    schema: String,
+    segments: Vec<MetaSegment>,
+    managed: Vec<ManagedFile>,
+    unmanaged: Vec<IndexFile>,
+    orphans: Vec<IndexFile>,
+    orphan_bytes: u64,
+}
+
+/// Files tantivy keeps next to the segments that are not tracked in `.managed.json`.
+fn is_bookkeeping_file(name: &str) -> bool {
+    name == META_FILENAME || name == MANAGED_FILENAME || name.ends_with(".lock")
+}
+
+pub fn meta(index_path: &Path) -> Result<MetaData, Error> {
+    let raw_meta: RawIndexMeta = serde_json::from_reader(File::open(index_path.join(META_FILENAME))?)?;
+    let managed: Vec<String> = match File::open(index_path.join(MANAGED_FILENAME)) {
+        Ok(file) => serde_json::from_reader(file)?,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e.into()),
+    };
+    let on_disk = list_index_files(index_path)?;
+
+    // meta.json stores hyphenated UUIDs, while segment files use the plain form.
+    let segments = raw_meta.segments
+        .into_iter()
+        .map(|x| MetaSegment {
+            id: x.segment_id.replace('-', ""),
+            max_doc: x.max_doc,
+            num_deleted_docs: x.deletes.as_ref().map(|d| d.num_deleted_docs),
+            delete_opstamp: x.deletes.as_ref().map(|d| d.opstamp),
+        })
+        .collect::<Vec<_>>();
+    // The delete file each live segment currently uses, if it has deletes at all.
+    let live_segments = segments.iter()
+        .map(|x| (x.id.clone(), x.delete_opstamp.map(|opstamp| format!("{}.{}.del", x.id, opstamp))))
+        .collect::<HashMap<_, _>>();
+
+    let on_disk_names = on_disk.iter().map(|x| x.name.clone()).collect::<HashSet<_>>();
+    let managed_names = managed.iter().cloned().collect::<HashSet<_>>();
+
+    let mut managed = managed
+        .into_iter()
+        .map(|name| ManagedFile {
+            on_disk: on_disk_names.contains(&name),
+            name,
+        })
+        .collect::<Vec<_>>();
+    managed.sort_by(|x, y| x.name.cmp(&y.name));
+
+    let unmanaged = on_disk.iter()
+        .filter(|x| !managed_names.contains(&x.name) && !is_bookkeeping_file(&x.name))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    // Left behind by merges or garbage collection that never completed, including delete
+    // files of live segments that have since been superseded by a newer delete opstamp.
+    let orphans = on_disk.iter()
+        .filter(|x| match x.segment.as_ref().map(|s| live_segments.get(s)) {
+            None => false,
+            Some(None) => true,
+            Some(Some(current_deletes)) => {
+                x.extension.as_ref().map(|x| x == "del").unwrap_or(false)
+                    && current_deletes.as_ref() != Some(&x.name)
+            }
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+    let orphan_bytes = orphans.iter().map(|x| x.size).sum();
+
+    Ok(MetaData {
+        opstamp: raw_meta.opstamp,
+        payload: raw_meta.payload,
+        schema: serde_json::to_string_pretty(&raw_meta.schema)?,
+        segments,
+        managed,
+        unmanaged,
+        orphans,
+        orphan_bytes,
+    })
+}
+
+pub(crate) fn handle_meta(req: HttpRequest<State>) -> Result<HttpResponse, Error> {
+    let state = req.state();
+    let data = meta(&state.index_path)?;
+    Ok(state.render_template("meta", &data)?)
+}
+
+pub fn run_meta(index_path: &Path, format: OutputFormat) -> Result<(), Error> {
+    let data = meta(index_path)?;
+    match format {
+        OutputFormat::Json => print_json(&data)?,
+        OutputFormat::Table => {
+            println!("Opstamp: {}", data.opstamp);
+            if let Some(ref payload) = data.payload {
+                println!("Payload: {}", payload);
+            }
+            println!();
+            let rows = data.segments.iter()
+                .map(|x| vec![
+                    x.id.clone(),
+                    x.max_doc.to_string(),
+                    x.num_deleted_docs.map(|x| x.to_string()).unwrap_or("-".to_string()),
+                    x.delete_opstamp.map(|x| x.to_string()).unwrap_or("-".to_string()),
+                ])
+                .collect::<Vec<_>>();
+            print_table(&["segment", "max doc", "deleted docs", "delete opstamp"], &rows);
+            println!();
+            let rows = data.managed.iter()
+                .filter(|x| !x.on_disk)
+                .map(|x| vec![x.name.clone(), "managed but missing".to_string(), "-".to_string()])
+                .chain(data.unmanaged.iter().map(|x| vec![x.name.clone(), "not managed".to_string(), x.size.to_string()]))
+                .chain(data.orphans.iter().map(|x| vec![x.name.clone(), "orphan".to_string(), x.size.to_string()]))
+                .collect::<Vec<_>>();
+            print_table(&["file", "problem", "bytes"], &rows);
+            println!();
+            println!("Orphans: {} bytes", data.orphan_bytes);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    const SEGMENT: &str = "0123456789abcdef0123456789abcdef";
+    const OLD_SEGMENT: &str = "fedcba9876543210fedcba9876543210";
+
+    #[test]
+    fn test_meta_finds_orphans_and_unmanaged_files() {
+        let dir = env::temp_dir().join(format!("tantivy-viewer-meta-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(META_FILENAME), r#"{
+            "segments": [{"segment_id": "01234567-89ab-cdef-0123-456789abcdef", "max_doc": 3, "deletes": {"num_deleted_docs": 1, "opstamp": 5}}],
+            "schema": [],
+            "opstamp": 6
+        }"#).unwrap();
+        let managed = vec![
+            format!("{}.store", SEGMENT),
+            format!("{}.5.del", SEGMENT),
+            format!("{}.4.del", SEGMENT),
+            format!("{}.idx", OLD_SEGMENT),
+            "gone.txt".to_string(),
+        ];
+        fs::write(dir.join(MANAGED_FILENAME), serde_json::to_string(&managed).unwrap()).unwrap();
+        for name in managed[..4].iter() {
+            fs::write(dir.join(name), "data").unwrap();
+        }
+        fs::write(dir.join("notes.txt"), "data").unwrap();
+
+        let data = meta(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(data.opstamp, 6);
+        assert_eq!(data.segments[0].id, SEGMENT);
+        assert_eq!(data.segments[0].num_deleted_docs, Some(1));
+        let missing = data.managed.iter().filter(|x| !x.on_disk).map(|x| &x.name[..]).collect::<Vec<_>>();
+        assert_eq!(missing, vec!["gone.txt"]);
+        let unmanaged = data.unmanaged.iter().map(|x| &x.name[..]).collect::<Vec<_>>();
+        assert_eq!(unmanaged, vec!["notes.txt"]);
+        let orphans = data.orphans.iter().map(|x| x.name.clone()).collect::<Vec<_>>();
+        assert_eq!(orphans, vec![format!("{}.4.del", SEGMENT), format!("{}.idx", OLD_SEGMENT)]);
+        assert_eq!(data.orphan_bytes, 8);
+    }
+}
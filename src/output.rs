@@ -0,0 +1,78 @@
+use failure::err_msg;
+use failure::Error;
+use serde::Serialize;
+use serde_json;
+
+use TantivyViewerError;
+
+/// How the report subcommands print their results.
+#[derive(Clone, Copy)]
+pub enum OutputFormat {
+    Table,
+    Json,
+}
+
+impl OutputFormat {
+    pub fn parse(format: Option<&str>) -> Result<OutputFormat, Error> {
+        match format {
+            None | Some("table") => Ok(OutputFormat::Table),
+            Some("json") => Ok(OutputFormat::Json),
+            Some(other) => Err(err_msg(format!("Unknown output format '{}'. Expected table or json", other))),
+        }
+    }
+}
+
+pub fn print_json<T: Serialize>(value: &T) -> Result<(), Error> {
+    let json = serde_json::to_string_pretty(value).map_err(|_e| TantivyViewerError::JsonSerializationError)?;
+    println!("{}", json);
+    Ok(())
+}
+
+/// Prints `rows` under `headers`, with each column padded to its widest cell.
+pub fn print_table(headers: &[&str], rows: &[Vec<String>]) {
+    print!("{}", format_table(headers, rows));
+}
+
+fn format_table(headers: &[&str], rows: &[Vec<String>]) -> String {
+    let mut widths = headers.iter().map(|x| x.chars().count()).collect::<Vec<_>>();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let format_row = |cells: &[String]| {
+        let line = cells.iter()
+            .zip(widths.iter())
+            .map(|(cell, &width)| format!("{:width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        format!("{}\n", line.trim_right())
+    };
+    let mut table = format_row(&headers.iter().map(|x| x.to_string()).collect::<Vec<_>>());
+    table.push_str(&format_row(&widths.iter().map(|&width| "-".repeat(width)).collect::<Vec<_>>()));
+    for row in rows {
+        table.push_str(&format_row(row));
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert!(match OutputFormat::parse(None).unwrap() { OutputFormat::Table => true, _ => false });
+        assert!(match OutputFormat::parse(Some("json")).unwrap() { OutputFormat::Json => true, _ => false });
+        assert!(OutputFormat::parse(Some("yaml")).is_err());
+    }
+
+    #[test]
+    fn test_format_table_pads_columns() {
+        let rows = vec![
+            vec!["title".to_string(), "1234".to_string()],
+            vec!["caf\u{e9}".to_string(), "5".to_string()],
+        ];
+        assert_eq!(format_table(&["field", "bytes"], &rows), "field  bytes\n-----  -----\ntitle  1234\ncaf\u{e9}   5\n");
+    }
+}
@@ -16,12 +16,26 @@ use std::collections::HashMap;
 use tantivy::SegmentId;
 use tantivy::postings::SegmentPostings;
 use tantivy::schema::Type;
+use tantivy::schema::TextOptions;
+use tantivy::schema::Schema;
+use tantivy::schema::Value;
+use tantivy::tokenizer::Token;
+use tantivy::tokenizer::TokenStream;
 use TantivyViewerError;
 use stringify_values;
 use actix_web::HttpRequest;
 use State;
 use actix_web::Query;
 use actix_web::HttpResponse;
+use segments::find_segment;
+use segments::disambiguation_page;
+use segments::SegmentMatch;
+use segments::doc_neighbours;
+use segments::resolve_segment;
+use output::print_json;
+use output::print_table;
+use output::OutputFormat;
+use url::form_urlencoded;
 
 trait FieldTypeExt {
     fn is_fast(&self) -> bool;
@@ -49,82 +63,89 @@ pub fn reconstruct_one(index: &Index, field: &str, segment: SegmentId, doc: DocI
 pub fn reconstruct(index: &Index, field: &str, docs: &HashMap<SegmentId, Vec<DocId>>) -> Result<HashMap<SegmentId, Vec<(DocId, Vec<Option<TantivyValue>>)>>, Error> {
     let schema = index.schema();
     let field = schema.get_field(field).ok_or(err_msg("Field not found"))?;
-    let field_type = schema.get_field_entry(field).field_type();
-    let value_type = field_type.value_type();
-    let options = field_type.get_index_record_option().unwrap_or(IndexRecordOption::WithFreqsAndPositions);
     let searcher = index.searcher();
 
-    let mut positions_buf = Vec::new();
     let mut reconstructed_docs = HashMap::new();
 
     for segment in searcher.segment_readers() {
         if let Some(segment_docs) = docs.get(&segment.segment_id()) {
-            let mut segment_reconstructed_docs = segment_docs.iter()
-                .map(|&doc| (doc, Vec::new()))
-                .collect::<Vec<_>>();
-            if let Some(_record_option) = field_type.get_index_record_option() {
-                // Field is indexed
-                let index = segment.inverted_index(field);
-                let mut term_stream = index.terms().stream();
-
-                while term_stream.advance() {
-                    let mut segment_postings = index.read_postings_from_terminfo(term_stream.value(), options);
-
-                    let mut current_doc = None;
-                    let mut reached_end = false;
-
-                    for (idx, &doc) in segment_docs.iter().enumerate() {
-                        let mut reconstructed_doc = &mut segment_reconstructed_docs.get_mut(idx).unwrap().1;
-                        let mut seek = !reached_end;
-                        if let Some(current_doc) = current_doc {
-                            if current_doc >= doc {
-                                seek = false;
-                            }
-                            if current_doc == doc {
-                                reconstruct_doc(&mut reconstructed_doc, &mut positions_buf, &mut segment_postings, term_stream.key(), value_type);
-                            }
-                        }
-                        if seek {
-                            match segment_postings.skip_next(doc) {
-                                SkipResult::Reached => {
-                                    reconstruct_doc(&mut reconstructed_doc, &mut positions_buf, &mut segment_postings, term_stream.key(), value_type);
-                                    current_doc = Some(segment_postings.doc());
-                                }
-                                SkipResult::End => {
-                                    reached_end = true;
-                                    current_doc = None;
-                                }
-                                SkipResult::OverStep => {
-                                    current_doc = Some(segment_postings.doc());
-                                }
-                            }
-                        }
+            let segment_reconstructed_docs = reconstruct_segment(&schema, segment, field, segment_docs)?;
+            reconstructed_docs.insert(segment.segment_id(), segment_reconstructed_docs);
+        }
+    }
+
+    Ok(reconstructed_docs)
+}
+
+/// Reconstructs `field` for `segment_docs` of a single segment, which must be sorted.
+pub(crate) fn reconstruct_segment(schema: &Schema, segment: &SegmentReader, field: Field, segment_docs: &[DocId]) -> Result<Vec<(DocId, Vec<Option<TantivyValue>>)>, Error> {
+    let field_type = schema.get_field_entry(field).field_type();
+    let value_type = field_type.value_type();
+    let options = field_type.get_index_record_option().unwrap_or(IndexRecordOption::WithFreqsAndPositions);
+    let mut positions_buf = Vec::new();
+
+    let mut segment_reconstructed_docs = segment_docs.iter()
+        .map(|&doc| (doc, Vec::new()))
+        .collect::<Vec<_>>();
+    if let Some(_record_option) = field_type.get_index_record_option() {
+        // Field is indexed
+        let index = segment.inverted_index(field);
+        let mut term_stream = index.terms().stream();
+
+        while term_stream.advance() {
+            let mut segment_postings = index.read_postings_from_terminfo(term_stream.value(), options);
+
+            let mut current_doc = None;
+            let mut reached_end = false;
+
+            for (idx, &doc) in segment_docs.iter().enumerate() {
+                let mut reconstructed_doc = &mut segment_reconstructed_docs.get_mut(idx).unwrap().1;
+                let mut seek = !reached_end;
+                if let Some(current_doc) = current_doc {
+                    if current_doc >= doc {
+                        seek = false;
+                    }
+                    if current_doc == doc {
+                        reconstruct_doc(&mut reconstructed_doc, &mut positions_buf, &mut segment_postings, term_stream.key(), value_type);
                     }
                 }
-            } else if field_type.is_fast() {
-                for (idx, &doc) in segment_docs.iter().enumerate() {
-                    let mut reconstructed_doc = &mut segment_reconstructed_docs.get_mut(idx).unwrap().1;
-                    match *field_type {
-                        FieldType::Str(_) => {},
-                        FieldType::U64(ref opts) => reconstruct_numeric::<u64>(segment, doc, field, opts.get_fastfield_cardinality(), &mut reconstructed_doc)?,
-                        FieldType::I64(ref opts) => reconstruct_numeric::<i64>(segment, doc, field, opts.get_fastfield_cardinality(), &mut reconstructed_doc)?,
-                        FieldType::HierarchicalFacet => unimplemented!(),
-                        FieldType::Bytes => {
-                            let bytes_reader = segment.bytes_fast_field_reader(field)?;
-                            let bytes = bytes_reader.get_val(doc).iter().cloned().collect::<Vec<_>>();
-                            reconstructed_doc.push(Some(bytes.into()));
-                        },
+                if seek {
+                    match segment_postings.skip_next(doc) {
+                        SkipResult::Reached => {
+                            reconstruct_doc(&mut reconstructed_doc, &mut positions_buf, &mut segment_postings, term_stream.key(), value_type);
+                            current_doc = Some(segment_postings.doc());
+                        }
+                        SkipResult::End => {
+                            reached_end = true;
+                            current_doc = None;
+                        }
+                        SkipResult::OverStep => {
+                            current_doc = Some(segment_postings.doc());
+                        }
                     }
                 }
             }
-            reconstructed_docs.insert(segment.segment_id(), segment_reconstructed_docs);
+        }
+    } else if field_type.is_fast() {
+        for (idx, &doc) in segment_docs.iter().enumerate() {
+            let mut reconstructed_doc = &mut segment_reconstructed_docs.get_mut(idx).unwrap().1;
+            match *field_type {
+                FieldType::Str(_) => {},
+                FieldType::U64(ref opts) => reconstruct_numeric::<u64>(segment, doc, field, opts.get_fastfield_cardinality(), &mut reconstructed_doc)?,
+                FieldType::I64(ref opts) => reconstruct_numeric::<i64>(segment, doc, field, opts.get_fastfield_cardinality(), &mut reconstructed_doc)?,
+                FieldType::HierarchicalFacet => unimplemented!(),
+                FieldType::Bytes => {
+                    let bytes_reader = segment.bytes_fast_field_reader(field)?;
+                    let bytes = bytes_reader.get_val(doc).iter().cloned().collect::<Vec<_>>();
+                    reconstructed_doc.push(Some(bytes.into()));
+                },
+            }
         }
     }
-
-    Ok(reconstructed_docs)
+    Ok(segment_reconstructed_docs)
 }
 
-fn reconstruct_doc(output: &mut Vec<Option<TantivyValue>>, positions_buf: &mut Vec<u32>, postings: &mut SegmentPostings, term_bytes: &[u8], value_type: Type) {
+pub(crate) fn reconstruct_doc(output: &mut Vec<Option<TantivyValue>>, positions_buf: &mut Vec<u32>, postings: &mut SegmentPostings, term_bytes: &[u8], value_type: Type) {
     postings.positions(positions_buf);
     let value = TantivyValue::from_term(term_bytes, value_type);
     if let Some(last) = positions_buf.pop() {
@@ -143,7 +164,23 @@ fn reconstruct_doc(output: &mut Vec<Option<TantivyValue>>, positions_buf: &mut V
     }
 }
 
-fn reconstruct_numeric<T: FastValue + Into<TantivyValue>>(segment: &SegmentReader, doc: DocId, field: Field, cardinality: Option<Cardinality>, output: &mut Vec<Option<TantivyValue>>) -> Result<(), Error> {
+/// Runs `text` through the tokenizer configured for a text field, as the indexer would have.
+pub(crate) fn tokenize(index: &Index, options: &TextOptions, text: &str) -> Result<Vec<Token>, Error> {
+    let tokenizer_name = options.get_indexing_options()
+        .map(|x| x.tokenizer())
+        .ok_or(err_msg("Field is not indexed"))?;
+    let tokenizer = index.tokenizers()
+        .get(tokenizer_name)
+        .ok_or(err_msg("Tokenizer not found"))?;
+    let mut tokens = Vec::new();
+    let mut token_stream = tokenizer.token_stream(text);
+    while token_stream.advance() {
+        tokens.push(token_stream.token().clone());
+    }
+    Ok(tokens)
+}
+
+pub(crate) fn reconstruct_numeric<T: FastValue + Into<TantivyValue>>(segment: &SegmentReader, doc: DocId, field: Field, cardinality: Option<Cardinality>, output: &mut Vec<Option<TantivyValue>>) -> Result<(), Error> {
     match cardinality {
         Some(Cardinality::SingleValue) => {
             let reader = segment.fast_field_reader::<T>(field)?;
@@ -163,19 +200,7 @@ fn reconstruct_numeric<T: FastValue + Into<TantivyValue>>(segment: &SegmentReade
     Ok(())
 }
 
-fn find_segment(index: &Index, segment_str: &str) -> Result<Option<SegmentId>, tantivy::Error> {
-    for segment_id in index.searchable_segment_ids()?.into_iter() {
-        if segment_id.uuid_string().starts_with(segment_str) {
-            return Ok(Some(segment_id));
-        }
-    }
-    Ok(None)
-}
-
-fn reconstruct_to_string(index: &Index, field: &str, segment: &str, doc: DocId) -> Result<String, Error> {
-    let segment = find_segment(index, segment)
-        .map_err(TantivyViewerError::TantivyError)?
-        .ok_or(TantivyViewerError::SegmentNotFoundError)?;
+fn reconstruct_to_string(index: &Index, field: &str, segment: SegmentId, doc: DocId) -> Result<String, Error> {
     Ok(
         stringify_values(reconstruct_one(index, field, segment, doc)?)
     )
@@ -192,53 +217,340 @@ pub struct ReconstructQuery {
 pub struct ReconstructEntry {
     field: String,
     contents: String,
+    has_positions: bool,
+}
+
+#[derive(Serialize)]
+pub struct NavigationLinks {
+    first: Option<String>,
+    prev: Option<String>,
+    next: Option<String>,
+    last: Option<String>,
+    next_segment: Option<String>,
+}
+
+fn reconstruct_href(field: &Option<String>, address: Option<(SegmentId, DocId)>) -> Option<String> {
+    address.map(|(segment, doc)| {
+        let mut query = form_urlencoded::Serializer::new(String::new());
+        if let Some(ref field) = *field {
+            query.append_pair("field", field);
+        }
+        query.append_pair("segment", &segment.uuid_string());
+        query.append_pair("doc", &doc.to_string());
+        format!("reconstruct?{}", query.finish())
+    })
 }
 
 #[derive(Serialize)]
 pub struct ReconstructData {
     segment: String,
     doc: DocId,
+    deleted: bool,
+    navigation: Option<NavigationLinks>,
     all_fields: bool,
     entries: Vec<ReconstructEntry>,
 }
 
+/// Reconstructs `field` of a doc, or every field when none is given, sorted by field name.
+fn reconstruct_entries(index: &Index, segment: SegmentId, doc: DocId, field: Option<String>) -> Result<Vec<ReconstructEntry>, Error> {
+    let schema = index.schema();
+    let mut fields = match field {
+        Some(field) => vec![field],
+        None => schema.fields().iter().map(|x| x.name().to_string()).collect(),
+    };
+    fields.sort();
+
+    let mut entries = Vec::new();
+    for field in fields {
+        let contents = reconstruct_to_string(index, &field, segment, doc)?;
+        let has_positions = schema.get_field(&field)
+            .and_then(|x| schema.get_field_entry(x).field_type().get_index_record_option())
+            .map(|x| x.has_positions())
+            .unwrap_or(false);
+        entries.push(ReconstructEntry {
+            field,
+            contents,
+            has_positions,
+        });
+    }
+    Ok(entries)
+}
+
+fn is_deleted(index: &Index, segment: SegmentId, doc: DocId) -> bool {
+    index.searcher()
+        .segment_readers()
+        .iter()
+        .find(|x| x.segment_id() == segment)
+        .map(|x| x.is_deleted(doc))
+        .unwrap_or(false)
+}
+
 pub(crate) fn handle_reconstruct(req: (HttpRequest<State>, Query<ReconstructQuery>)) -> Result<HttpResponse, Error> {
     let (req, params) = req;
     let state = req.state();
     let field = params.field.clone();
-    let segment = params.segment.clone();
+    let segment = match find_segment(&state.index, &params.segment).map_err(TantivyViewerError::TantivyError)? {
+        SegmentMatch::Found(segment) => segment,
+        SegmentMatch::Ambiguous(candidates) => return Ok(disambiguation_page(&req, "segment", &params.segment, candidates)?),
+        SegmentMatch::NotFound => return Err(TantivyViewerError::SegmentNotFoundError.into()),
+    };
     let doc = params.doc;
+    let deleted = is_deleted(&state.index, segment, doc);
+    let navigation = doc_neighbours(&state.index, segment, doc)
+        .map(|x| NavigationLinks {
+            first: reconstruct_href(&params.field, x.first),
+            prev: reconstruct_href(&params.field, x.prev),
+            next: reconstruct_href(&params.field, x.next),
+            last: reconstruct_href(&params.field, x.last),
+            next_segment: reconstruct_href(&params.field, x.next_segment),
+        });
 
-    let mut fields = Vec::new();
     let all_fields = field.is_none();
-    if let Some(field) = field {
-        // Reconstruct a specific field
-        fields.push(field);
-    } else {
-        // Reconstruct all fields
-        let schema = state.index.schema();
-        fields.extend(schema.fields().iter().map(|x| x.name().to_string()));
+    let entries = reconstruct_entries(&state.index, segment, doc, field)?;
+
+    let data = ReconstructData {
+        segment: segment.uuid_string(),
+        doc,
+        deleted,
+        navigation,
+        all_fields,
+        entries,
+    };
+
+    Ok(state.render_template("reconstruct", &data)?)
+}
+
+pub fn run_reconstruct(index: &Index, segment: &str, doc: DocId, field: Option<&str>, format: OutputFormat) -> Result<(), Error> {
+    let segment = resolve_segment(index, segment)?;
+    let data = ReconstructData {
+        segment: segment.uuid_string(),
+        doc,
+        deleted: is_deleted(index, segment, doc),
+        navigation: None,
+        all_fields: field.is_none(),
+        entries: reconstruct_entries(index, segment, doc, field.map(|x| x.to_string()))?,
+    };
+    match format {
+        OutputFormat::Json => print_json(&data)?,
+        OutputFormat::Table => {
+            if data.deleted {
+                println!("Doc {} of segment {} is deleted", doc, data.segment);
+            }
+            let rows = data.entries.iter()
+                .map(|x| vec![x.field.clone(), x.contents.trim_right().to_string()])
+                .collect::<Vec<_>>();
+            print_table(&["field", "contents"], &rows);
+        }
     }
+    Ok(())
+}
 
-    fields.sort();
+#[derive(Serialize)]
+pub struct PositionEntry {
+    position: usize,
+    text: Option<String>,
+    gap_length: usize,
+}
 
-    let mut all_reconstructed = Vec::new();
+#[derive(Serialize)]
+pub struct TextSpan {
+    text: String,
+    is_token: bool,
+    position: Option<usize>,
+    mismatch: bool,
+}
 
-    for field in fields {
-        let contents = reconstruct_to_string(&state.index, &field, &segment, doc)?;
+#[derive(Serialize)]
+pub struct PositionsData {
+    segment: String,
+    doc: DocId,
+    field: String,
+    tokens: Vec<PositionEntry>,
+    original: Vec<Vec<TextSpan>>,
+}
 
-        all_reconstructed.push(ReconstructEntry {
-            field,
-            contents
-        });
+/// Collapses a positional reconstruction into tokens and runs of missing positions.
+fn position_entries(values: &[Option<TantivyValue>]) -> Vec<PositionEntry> {
+    let mut entries: Vec<PositionEntry> = Vec::new();
+    for (position, value) in values.iter().enumerate() {
+        match *value {
+            Some(ref value) => entries.push(PositionEntry {
+                position,
+                text: Some(format!("{}", value)),
+                gap_length: 0,
+            }),
+            None => {
+                let extends_gap = entries.last().map(|x| x.text.is_none()).unwrap_or(false);
+                if extends_gap {
+                    entries.last_mut().unwrap().gap_length += 1;
+                } else {
+                    entries.push(PositionEntry { position, text: None, gap_length: 1 });
+                }
+            }
+        }
     }
+    entries
+}
 
-    let data = ReconstructData {
-        segment,
+/// Lines the stored text up with the indexed positions by running it through the field's
+/// tokenizer again, since this version of tantivy does not index offsets.
+///
+/// Each stored value continues the positions of the previous one, mirroring how the
+/// indexer chains the token streams of a multi-valued field.
+fn align_stored_text(index: &Index, options: &TextOptions, texts: &[&str], values: &[Option<TantivyValue>]) -> Result<Vec<Vec<TextSpan>>, Error> {
+    let mut result = Vec::new();
+    let mut position_shift = 0;
+    for &text in texts {
+        let mut spans = Vec::new();
+        let mut last_offset = 0;
+        let mut next_shift = position_shift;
+        for token in tokenize(index, options, text)? {
+            let (from, to) = (token.offset_from, token.offset_to);
+            if from < last_offset || text.get(from..to).is_none() || text.get(last_offset..from).is_none() {
+                continue;
+            }
+            if from > last_offset {
+                spans.push(TextSpan { text: text[last_offset..from].to_string(), is_token: false, position: None, mismatch: false });
+            }
+            let position = position_shift + token.position;
+            let indexed = values.get(position).and_then(|x| x.as_ref()).map(|x| format!("{}", x));
+            spans.push(TextSpan {
+                text: text[from..to].to_string(),
+                is_token: true,
+                position: Some(position),
+                mismatch: indexed.as_ref() != Some(&token.text),
+            });
+            last_offset = to;
+            next_shift = position + 1;
+        }
+        if last_offset < text.len() {
+            spans.push(TextSpan { text: text[last_offset..].to_string(), is_token: false, position: None, mismatch: false });
+        }
+        position_shift = next_shift;
+        result.push(spans);
+    }
+    Ok(result)
+}
+
+pub fn positions(index: &Index, field_name: &str, segment_id: SegmentId, doc: DocId) -> Result<PositionsData, Error> {
+    let schema = index.schema();
+    let field = schema.get_field(field_name).ok_or(err_msg("Field not found"))?;
+    let field_entry = schema.get_field_entry(field);
+    let options = match *field_entry.field_type() {
+        FieldType::Str(ref options) => options,
+        _ => return Err(err_msg("Only text fields have token positions")),
+    };
+    let has_positions = field_entry.field_type().get_index_record_option().map(|x| x.has_positions()).unwrap_or(false);
+    if !has_positions {
+        return Err(err_msg("This field is not indexed with positions"));
+    }
+
+    let values = reconstruct_one(index, field_name, segment_id, doc)?;
+
+    let original = if field_entry.is_stored() {
+        let searcher = index.searcher();
+        let segment = searcher.segment_readers().iter()
+            .find(|x| x.segment_id() == segment_id)
+            .ok_or(TantivyViewerError::SegmentNotFoundError)?;
+        let stored = segment.doc(doc).map_err(TantivyViewerError::TantivyError)?;
+        let texts = stored.get_all(field)
+            .into_iter()
+            .filter_map(|x| match *x {
+                Value::Str(ref text) => Some(text.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        align_stored_text(index, options, &texts, &values)?
+    } else {
+        Vec::new()
+    };
+
+    Ok(PositionsData {
+        segment: segment_id.uuid_string(),
         doc,
-        all_fields,
-        entries: all_reconstructed,
+        field: field_name.to_string(),
+        tokens: position_entries(&values),
+        original,
+    })
+}
+
+#[derive(Deserialize)]
+pub struct PositionsQuery {
+    field: String,
+    segment: String,
+    doc: DocId,
+}
+
+pub(crate) fn handle_positions(req: (HttpRequest<State>, Query<PositionsQuery>)) -> Result<HttpResponse, Error> {
+    let (req, params) = req;
+    let state = req.state();
+    let segment = match find_segment(&state.index, &params.segment).map_err(TantivyViewerError::TantivyError)? {
+        SegmentMatch::Found(segment) => segment,
+        SegmentMatch::Ambiguous(candidates) => return Ok(disambiguation_page(&req, "segment", &params.segment, candidates)?),
+        SegmentMatch::NotFound => return Err(TantivyViewerError::SegmentNotFoundError.into()),
     };
+    let data = positions(&state.index, &params.field, segment, params.doc)?;
+    Ok(state.render_template("positions", &data)?)
+}
 
-    Ok(state.render_template("reconstruct", &data)?)
-}
\ No newline at end of file
+pub fn run_positions(index: &Index, segment: &str, doc: DocId, field: &str, format: OutputFormat) -> Result<(), Error> {
+    let data = positions(index, field, resolve_segment(index, segment)?, doc)?;
+    match format {
+        OutputFormat::Json => print_json(&data)?,
+        OutputFormat::Table => {
+            let rows = data.tokens.iter()
+                .map(|x| match x.text {
+                    Some(ref text) => vec![x.position.to_string(), text.clone()],
+                    None => vec![x.position.to_string(), format!("[{} missing positions]", x.gap_length)],
+                })
+                .collect::<Vec<_>>();
+            print_table(&["position", "token"], &rows);
+            for (idx, spans) in data.original.iter().enumerate() {
+                for span in spans.iter().filter(|x| x.mismatch) {
+                    println!("Stored value {} has '{}' at position {}, which the index does not", idx, span.text, span.position.unwrap());
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tantivy::schema::SchemaBuilder;
+    use tantivy::schema::TEXT;
+
+    fn text(value: &str) -> Option<TantivyValue> {
+        Some(TantivyValue::Text(value.to_string()))
+    }
+
+    #[test]
+    fn test_position_entries_collapse_gaps() {
+        let entries = position_entries(&[text("a"), None, None, text("b")]);
+        let entries = entries.iter().map(|x| (x.position, x.text.clone(), x.gap_length)).collect::<Vec<_>>();
+        assert_eq!(entries, vec![(0, Some("a".to_string()), 0), (1, None, 2), (3, Some("b".to_string()), 0)]);
+    }
+
+    #[test]
+    fn test_align_stored_text_continues_positions_across_values() {
+        let mut schema_builder = SchemaBuilder::default();
+        let body = schema_builder.add_text_field("body", TEXT);
+        let index = Index::create_in_ram(schema_builder.build());
+        let schema = index.schema();
+        let options = match *schema.get_field_entry(body).field_type() {
+            FieldType::Str(ref options) => options.clone(),
+            _ => unreachable!(),
+        };
+
+        let values = vec![text("hello"), text("word"), text("again")];
+        let aligned = align_stored_text(&index, &options, &["Hello world", "again!"], &values).unwrap();
+        let aligned = aligned.iter()
+            .map(|spans| spans.iter().map(|x| (&x.text[..], x.position, x.mismatch)).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(aligned, vec![
+            vec![("Hello", Some(0), false), (" ", None, false), ("world", Some(1), true)],
+            vec![("again", Some(2), false), ("!", None, false)],
+        ]);
+    }
+}
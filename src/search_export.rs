@@ -0,0 +1,269 @@
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use bytes::Bytes;
+use failure::err_msg;
+use failure::Error;
+use serde_json;
+use tantivy::DocId;
+use tantivy::Document;
+use tantivy::Index;
+use tantivy::Score;
+use tantivy::query::QueryParser;
+use tantivy::schema::Field;
+use tantivy::schema::Value;
+use url::form_urlencoded;
+
+use export::csv_field;
+use export::present_values;
+use export::streaming_download;
+use export::value_to_json;
+use export::write_export;
+use export::DocBatch;
+use export::DocBatches;
+use export::ExportFormat;
+use top_terms::TantivyValue;
+use State;
+use TantivyViewerError;
+
+/// Which columns to write for each matching doc.
+pub struct SearchExportColumns {
+    pub address: bool,
+    pub score: bool,
+    pub reconstructed: Vec<String>,
+    pub stored: Vec<String>,
+}
+
+fn stored_values(doc: &Document, field: Field) -> Vec<TantivyValue> {
+    doc.get_all(field)
+        .into_iter()
+        .filter_map(|value| match *value {
+            Value::Facet(ref facet) => Some(TantivyValue::Text(format!("{}", facet))),
+            _ => TantivyValue::from_value(value),
+        })
+        .collect()
+}
+
+/// Writes every live doc matching a query, a segment at a time and in batches within it.
+pub struct SearchExport {
+    batches: DocBatches,
+    columns: SearchExportColumns,
+    stored_fields: Vec<Field>,
+    format: ExportFormat,
+    header_written: bool,
+}
+
+impl SearchExport {
+    pub fn new(index: &Index, query: &::tantivy::query::Query, columns: SearchExportColumns, format: ExportFormat) -> Result<SearchExport, Error> {
+        if !columns.address && !columns.score && columns.reconstructed.is_empty() && columns.stored.is_empty() {
+            return Err(err_msg("Pick at least one column to export"));
+        }
+        let schema = index.schema();
+        let mut stored_fields = Vec::new();
+        for field_name in columns.stored.iter() {
+            let field = schema.get_field(field_name).ok_or(err_msg("Sorry, that field does not exist!"))?;
+            if !schema.get_field_entry(field).is_stored() {
+                return Err(err_msg(format!("Field '{}' is not stored", field_name)));
+            }
+            stored_fields.push(field);
+        }
+        Ok(SearchExport {
+            batches: DocBatches::new(index, Some(query), columns.score, &columns.reconstructed)?,
+            columns,
+            stored_fields,
+            format,
+            header_written: false,
+        })
+    }
+
+    fn header(&self) -> String {
+        let mut columns = Vec::new();
+        if self.columns.address {
+            columns.push("segment".to_string());
+            columns.push("doc".to_string());
+        }
+        if self.columns.score {
+            columns.push("score".to_string());
+        }
+        columns.extend(self.columns.reconstructed.iter().map(|x| csv_field(x)));
+        columns.extend(self.columns.stored.iter().map(|x| csv_field(&format!("stored:{}", x))));
+        format!("{}\n", columns.join(","))
+    }
+
+    fn write_batch(&self, batch: DocBatch) -> Result<Bytes, Error> {
+        let segment_id = batch.segment.segment_id().uuid_string();
+        let mut reconstructed = batch.values.into_iter().map(|x| x.into_iter()).collect::<Vec<_>>();
+        let mut output = String::new();
+        for (doc, score) in batch.docs {
+            let reconstructed_values = reconstructed.iter_mut()
+                .map(|values| present_values(values.next().unwrap_or_default()))
+                .collect::<Vec<_>>();
+            let stored_columns = if self.stored_fields.is_empty() {
+                Vec::new()
+            } else {
+                let stored = batch.segment.doc(doc).map_err(TantivyViewerError::TantivyError)?;
+                self.stored_fields.iter().map(|&field| stored_values(&stored, field)).collect()
+            };
+            output.push_str(&self.row(&segment_id, doc, score, reconstructed_values, stored_columns)?);
+        }
+        Ok(output.into())
+    }
+
+    fn row(&self, segment: &str, doc: DocId, score: Score, reconstructed: Vec<Vec<TantivyValue>>, stored: Vec<Vec<TantivyValue>>) -> Result<String, Error> {
+        Ok(match self.format {
+            ExportFormat::Ndjson => {
+                let mut row = serde_json::Map::new();
+                if self.columns.address {
+                    row.insert("segment".to_string(), segment.into());
+                    row.insert("doc".to_string(), doc.into());
+                }
+                if self.columns.score {
+                    row.insert("score".to_string(), (score as f64).into());
+                }
+                let to_object = |names: &[String], values: Vec<Vec<TantivyValue>>| {
+                    let object = names.iter()
+                        .cloned()
+                        .zip(values.into_iter().map(|x| serde_json::Value::Array(x.iter().map(value_to_json).collect())))
+                        .collect::<serde_json::Map<_, _>>();
+                    serde_json::Value::Object(object)
+                };
+                if !self.columns.reconstructed.is_empty() {
+                    row.insert("reconstructed".to_string(), to_object(&self.columns.reconstructed[..], reconstructed));
+                }
+                if !self.columns.stored.is_empty() {
+                    row.insert("stored".to_string(), to_object(&self.columns.stored[..], stored));
+                }
+                let json = serde_json::to_string(&serde_json::Value::Object(row)).map_err(|_e| TantivyViewerError::JsonSerializationError)?;
+                format!("{}\n", json)
+            }
+            ExportFormat::Csv => {
+                let mut columns = Vec::new();
+                if self.columns.address {
+                    columns.push(segment.to_string());
+                    columns.push(doc.to_string());
+                }
+                if self.columns.score {
+                    columns.push(score.to_string());
+                }
+                for values in reconstructed.into_iter().chain(stored) {
+                    let joined = values.iter().map(|x| format!("{}", x)).collect::<Vec<_>>().join(" ");
+                    columns.push(csv_field(&joined));
+                }
+                format!("{}\n", columns.join(","))
+            }
+        })
+    }
+}
+
+impl Iterator for SearchExport {
+    type Item = Result<Bytes, Error>;
+
+    fn next(&mut self) -> Option<Result<Bytes, Error>> {
+        if !self.header_written {
+            self.header_written = true;
+            if let ExportFormat::Csv = self.format {
+                return Some(Ok(self.header().into()));
+            }
+        }
+        match self.batches.next_batch() {
+            Ok(Some(batch)) => Some(self.write_batch(batch)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// Takes repeated `reconstructed` and `stored` parameters, one per column, so the
+/// query string is parsed by hand rather than through `Query`.
+pub(crate) fn handle_search_export(req: HttpRequest<State>) -> Result<HttpResponse, Error> {
+    let state = req.state();
+    let mut columns = SearchExportColumns {
+        address: false,
+        score: false,
+        reconstructed: Vec::new(),
+        stored: Vec::new(),
+    };
+    let mut raw_query = None;
+    let mut format = None;
+    for (key, value) in form_urlencoded::parse(req.query_string().as_bytes()) {
+        match &*key {
+            "query" => raw_query = Some(value.into_owned()),
+            "format" => format = Some(value.into_owned()),
+            "address" => columns.address = true,
+            "score" => columns.score = true,
+            "reconstructed" => columns.reconstructed.push(value.into_owned()),
+            "stored" => columns.stored.push(value.into_owned()),
+            _ => {},
+        }
+    }
+
+    let raw_query = raw_query.ok_or(err_msg("Missing query"))?;
+    let format = ExportFormat::parse(format.as_ref().map(|x| x.as_str()))?;
+    let query_parser = QueryParser::for_index(&state.index, vec![]);
+    let query = query_parser.parse_query(&raw_query).map_err(TantivyViewerError::QueryParserError)?;
+    let export = SearchExport::new(&state.index, &*query, columns, format)?;
+    Ok(streaming_download(export, format, "search_results"))
+}
+
+pub fn run_search_export(index: &Index, raw_query: &str, columns: SearchExportColumns, format: ExportFormat) -> Result<(), Error> {
+    let query_parser = QueryParser::for_index(index, vec![]);
+    let query = query_parser.parse_query(raw_query).map_err(TantivyViewerError::QueryParserError)?;
+    write_export(SearchExport::new(index, &*query, columns, format)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tantivy::schema::SchemaBuilder;
+    use tantivy::schema::STORED;
+    use tantivy::schema::STRING;
+    use tantivy::schema::TEXT;
+
+    fn test_index() -> Index {
+        let mut schema_builder = SchemaBuilder::default();
+        let id = schema_builder.add_text_field("id", STRING);
+        let title = schema_builder.add_text_field("title", TEXT | STORED);
+        let index = Index::create_in_ram(schema_builder.build());
+        let mut writer = index.writer_with_num_threads(1, 40_000_000).unwrap();
+        for &(id_value, title_value) in &[("a", "Red, apple"), ("b", "green pear"), ("c", "red cherry")] {
+            let mut document = Document::default();
+            document.add_text(id, id_value);
+            document.add_text(title, title_value);
+            writer.add_document(document);
+        }
+        writer.commit().unwrap();
+        index.load_searchers().unwrap();
+        index
+    }
+
+    fn columns(reconstructed: &[&str], stored: &[&str]) -> SearchExportColumns {
+        SearchExportColumns {
+            address: false,
+            score: false,
+            reconstructed: reconstructed.iter().map(|x| x.to_string()).collect(),
+            stored: stored.iter().map(|x| x.to_string()).collect(),
+        }
+    }
+
+    fn export_text(index: &Index, query: &str, columns: SearchExportColumns, format: ExportFormat) -> Result<String, Error> {
+        let query = QueryParser::for_index(index, vec![]).parse_query(query).unwrap();
+        let export = SearchExport::new(index, &*query, columns, format)?;
+        let chunks = export.collect::<Result<Vec<_>, _>>()?;
+        Ok(chunks.iter().map(|x| String::from_utf8(x.to_vec()).unwrap()).collect())
+    }
+
+    #[test]
+    fn test_search_export_columns() {
+        let index = test_index();
+        let csv = export_text(&index, "title:red", columns(&["id"], &["title"]), ExportFormat::Csv).unwrap();
+        assert_eq!(csv, "id,stored:title\na,\"Red, apple\"\nc,red cherry\n");
+        let ndjson = export_text(&index, "title:pear", columns(&["id"], &[]), ExportFormat::Ndjson).unwrap();
+        assert_eq!(ndjson, "{\"reconstructed\":{\"id\":[\"b\"]}}\n");
+    }
+
+    #[test]
+    fn test_search_export_rejects_bad_columns() {
+        let index = test_index();
+        assert!(export_text(&index, "title:red", columns(&[], &[]), ExportFormat::Csv).is_err());
+        assert!(export_text(&index, "title:red", columns(&[], &["id"]), ExportFormat::Csv).is_err());
+    }
+}
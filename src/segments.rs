@@ -0,0 +1,643 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use actix_web::Query;
+use failure::err_msg;
+use failure::Error;
+use tantivy::Index;
+use tantivy::SegmentId;
+use tantivy::SegmentMeta;
+use tantivy::SegmentReader;
+use tantivy::space_usage::PerFieldSpaceUsage;
+use tantivy::space_usage::SegmentSpaceUsage;
+use tantivy::schema::Field;
+
+use actix_web::Path;
+use actix_web::http;
+use tantivy::DocId;
+use url::form_urlencoded;
+
+use get_identifying_fields;
+use lookup::describe_matches;
+use lookup::DocMatch;
+use output::print_json;
+use output::print_table;
+use output::OutputFormat;
+use reconstruct::reconstruct;
+use top_terms::TantivyValue;
+use State;
+use TantivyViewerError;
+
+pub enum SegmentMatch {
+    Found(SegmentId),
+    Ambiguous(Vec<SegmentId>),
+    NotFound,
+}
+
+/// Resolves a segment from its full UUID or any prefix of it.
+///
+/// A prefix shared by several segments is reported as ambiguous rather than
+/// silently picking one of them.
+pub(crate) fn find_segment(index: &Index, segment_str: &str) -> Result<SegmentMatch, tantivy::Error> {
+    let mut candidates = Vec::new();
+    for segment_id in index.searchable_segment_ids()?.into_iter() {
+        let uuid_string = segment_id.uuid_string();
+        if uuid_string == segment_str {
+            return Ok(SegmentMatch::Found(segment_id));
+        }
+        if uuid_string.starts_with(segment_str) {
+            candidates.push(segment_id);
+        }
+    }
+    Ok(match candidates.len() {
+        0 => SegmentMatch::NotFound,
+        1 => SegmentMatch::Found(candidates.pop().unwrap()),
+        _ => SegmentMatch::Ambiguous(candidates),
+    })
+}
+
+/// Resolves a segment id or unique prefix, for subcommands that can't show a chooser.
+pub(crate) fn resolve_segment(index: &Index, segment: &str) -> Result<SegmentId, Error> {
+    match find_segment(index, segment).map_err(TantivyViewerError::TantivyError)? {
+        SegmentMatch::Found(segment) => Ok(segment),
+        SegmentMatch::Ambiguous(candidates) => Err(err_msg(format!(
+            "Segment prefix '{}' is ambiguous: {}",
+            segment,
+            candidates.iter().map(|x| x.uuid_string()).collect::<Vec<_>>().join(", "),
+        ))),
+        SegmentMatch::NotFound => Err(TantivyViewerError::SegmentNotFoundError.into()),
+    }
+}
+
+#[derive(Serialize)]
+struct DisambiguationCandidate {
+    id: String,
+    href: String,
+}
+
+#[derive(Serialize)]
+struct DisambiguationData {
+    prefix: String,
+    candidates: Vec<DisambiguationCandidate>,
+}
+
+/// Renders a page linking to the current request once per candidate segment,
+/// with the `param` query parameter replaced by the candidate's full UUID.
+pub(crate) fn disambiguation_page(req: &HttpRequest<State>, param: &str, prefix: &str, candidates: Vec<SegmentId>) -> Result<HttpResponse, TantivyViewerError> {
+    let path = req.path().rsplit('/').next().unwrap_or("").to_string();
+    let candidates = candidates.into_iter()
+        .map(|segment_id| {
+            let id = segment_id.uuid_string();
+            let query = form_urlencoded::Serializer::new(String::new())
+                .extend_pairs(form_urlencoded::parse(req.query_string().as_bytes())
+                    .map(|(k, v)| if k == param { (k.into_owned(), id.clone()) } else { (k.into_owned(), v.into_owned()) }))
+                .finish();
+            DisambiguationCandidate {
+                href: format!("{}?{}", path, query),
+                id,
+            }
+        })
+        .collect();
+
+    req.state().render_template("segment_disambiguation", &DisambiguationData {
+        prefix: prefix.to_string(),
+        candidates,
+    })
+}
+
+/// Maps an ordinal counting docs across all segments, in searcher order, to a doc address.
+pub fn segment_for_ordinal(index: &Index, ordinal: u64) -> Option<(SegmentId, DocId)> {
+    let searcher = index.searcher();
+    let mut remaining = ordinal;
+    for segment in searcher.segment_readers() {
+        let max_doc = segment.max_doc() as u64;
+        if remaining < max_doc {
+            return Some((segment.segment_id(), remaining as DocId));
+        }
+        remaining -= max_doc;
+    }
+    None
+}
+
+#[derive(Deserialize)]
+pub struct DocOrdinalPath {
+    ordinal: u64,
+}
+
+pub(crate) fn handle_doc_ordinal(req: (HttpRequest<State>, Path<DocOrdinalPath>)) -> Result<HttpResponse, TantivyViewerError> {
+    let (req, path) = req;
+    let state = req.state();
+    let (segment, doc) = segment_for_ordinal(&state.index, path.ordinal)
+        .ok_or(TantivyViewerError::DocOrdinalOutOfRangeError)?;
+    Ok(
+        HttpResponse::Found()
+            .header(http::header::LOCATION, format!("../reconstruct?segment={}&doc={}", segment.uuid_string(), doc))
+            .finish()
+    )
+}
+
+/// Live docs around a doc, for stepping through a segment one doc at a time.
+pub struct DocNeighbours {
+    pub first: Option<(SegmentId, DocId)>,
+    pub prev: Option<(SegmentId, DocId)>,
+    pub next: Option<(SegmentId, DocId)>,
+    pub last: Option<(SegmentId, DocId)>,
+    pub next_segment: Option<(SegmentId, DocId)>,
+}
+
+fn first_live_doc(segment: &SegmentReader) -> Option<DocId> {
+    (0..segment.max_doc()).find(|&doc| !segment.is_deleted(doc))
+}
+
+pub fn doc_neighbours(index: &Index, segment_id: SegmentId, doc: DocId) -> Option<DocNeighbours> {
+    let searcher = index.searcher();
+    let segment_readers = searcher.segment_readers();
+    let position = segment_readers.iter().position(|x| x.segment_id() == segment_id)?;
+    let segment = &segment_readers[position];
+    let max_doc = segment.max_doc();
+    let with_segment = |doc: Option<DocId>| doc.map(|doc| (segment_id, doc));
+
+    Some(DocNeighbours {
+        first: with_segment(first_live_doc(segment)),
+        prev: with_segment((0..doc.min(max_doc)).rev().find(|&doc| !segment.is_deleted(doc))),
+        next: with_segment((doc.saturating_add(1)..max_doc).find(|&doc| !segment.is_deleted(doc))),
+        last: with_segment((0..max_doc).rev().find(|&doc| !segment.is_deleted(doc))),
+        next_segment: segment_readers[position + 1..]
+            .iter()
+            .filter_map(|segment| first_live_doc(segment).map(|doc| (segment.segment_id(), doc)))
+            .next(),
+    })
+}
+
+/// Picks a live doc uniformly at random.
+///
+/// There is no `rand` dependency, so the pick is seeded from the clock and mixed with
+/// the splitmix64 finalizer, which is plenty for sampling docs by hand.
+pub fn random_live_doc(index: &Index) -> Option<(SegmentId, DocId)> {
+    let searcher = index.searcher();
+    let num_docs = searcher.segment_readers().iter().map(|x| x.num_docs() as u64).sum::<u64>();
+    if num_docs == 0 {
+        return None;
+    }
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or(Duration::from_secs(0));
+    let mut seed = now.as_secs().wrapping_mul(1_000_000_000).wrapping_add(now.subsec_nanos() as u64);
+    seed = (seed ^ (seed >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    seed = (seed ^ (seed >> 27)).wrapping_mul(0x94d049bb133111eb);
+    seed ^= seed >> 31;
+
+    let mut remaining = seed % num_docs;
+    for segment in searcher.segment_readers() {
+        let num_docs = segment.num_docs() as u64;
+        if remaining < num_docs {
+            return (0..segment.max_doc())
+                .filter(|&doc| !segment.is_deleted(doc))
+                .nth(remaining as usize)
+                .map(|doc| (segment.segment_id(), doc));
+        }
+        remaining -= num_docs;
+    }
+    None
+}
+
+pub(crate) fn handle_random_doc(req: HttpRequest<State>) -> Result<HttpResponse, TantivyViewerError> {
+    let state = req.state();
+    let (segment, doc) = random_live_doc(&state.index)
+        .ok_or(TantivyViewerError::NoLiveDocsError)?;
+    Ok(
+        HttpResponse::Found()
+            .header(http::header::LOCATION, format!("reconstruct?segment={}&doc={}", segment.uuid_string(), doc))
+            .finish()
+    )
+}
+
+#[derive(Serialize)]
+pub struct SegmentSummary {
+    id: String,
+    short_id: String,
+    max_doc: u32,
+    num_docs: u32,
+    num_deleted_docs: u32,
+    deleted_ratio: String,
+    delete_opstamp: Option<u64>,
+    total_bytes: usize,
+    files: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct SegmentsData {
+    segments: Vec<SegmentSummary>,
+}
+
+fn deleted_ratio(num_deleted_docs: u32, max_doc: u32) -> String {
+    if max_doc == 0 {
+        "0.0%".to_string()
+    } else {
+        format!("{:.1}%", num_deleted_docs as f64 * 100.0 / max_doc as f64)
+    }
+}
+
+fn segment_summary(segment: &SegmentReader, usage: &SegmentSpaceUsage, meta: Option<&SegmentMeta>) -> SegmentSummary {
+    let segment_id = segment.segment_id();
+    let (delete_opstamp, mut files) = match meta {
+        Some(meta) => (
+            meta.delete_opstamp(),
+            meta.list_files().into_iter().map(|x| x.to_string_lossy().into_owned()).collect::<Vec<_>>(),
+        ),
+        None => (None, Vec::new()),
+    };
+    files.sort();
+    SegmentSummary {
+        id: segment_id.uuid_string(),
+        short_id: segment_id.short_uuid_string(),
+        max_doc: segment.max_doc(),
+        num_docs: segment.num_docs(),
+        num_deleted_docs: segment.num_deleted_docs(),
+        deleted_ratio: deleted_ratio(segment.num_deleted_docs(), segment.max_doc()),
+        delete_opstamp,
+        total_bytes: usage.total(),
+        files,
+    }
+}
+
+pub fn segments(index: &Index) -> Result<SegmentsData, Error> {
+    let searcher = index.searcher();
+    let space_usage = searcher.space_usage();
+    let metas = index.searchable_segment_metas()
+        .map_err(TantivyViewerError::TantivyError)?
+        .into_iter()
+        .map(|meta| (meta.id(), meta))
+        .collect::<HashMap<_, _>>();
+
+    let segments = searcher.segment_readers()
+        .iter()
+        .zip(space_usage.segments())
+        .map(|(segment, usage)| segment_summary(segment, usage, metas.get(&segment.segment_id())))
+        .collect();
+
+    Ok(SegmentsData { segments })
+}
+
+pub(crate) fn handle_segments(req: HttpRequest<State>) -> Result<HttpResponse, Error> {
+    let state = req.state();
+    let data = segments(&state.index)?;
+    Ok(state.render_template("segments", &data)?)
+}
+
+#[derive(Serialize)]
+pub struct SegmentFieldStats {
+    name: String,
+    num_terms: Option<usize>,
+    termdict: usize,
+    postings: usize,
+    positions: usize,
+    fast_fields: usize,
+    fieldnorms: usize,
+    total: usize,
+}
+
+#[derive(Serialize)]
+pub struct SegmentDetail {
+    summary: SegmentSummary,
+    store: usize,
+    deletes: usize,
+    fields: Vec<SegmentFieldStats>,
+}
+
+fn field_usage(usage: &PerFieldSpaceUsage, field: Field) -> usize {
+    usage.fields()
+        .filter(|&(x, _)| *x == field)
+        .map(|(_, usage)| usage.total())
+        .sum()
+}
+
+pub fn segment_detail(index: &Index, segment_id: SegmentId) -> Result<SegmentDetail, Error> {
+    let schema = index.schema();
+    let searcher = index.searcher();
+    let segment = searcher.segment_readers()
+        .iter()
+        .find(|x| x.segment_id() == segment_id)
+        .ok_or(TantivyViewerError::SegmentNotFoundError)?;
+    let usage = segment.space_usage();
+    let meta = index.searchable_segment_metas()
+        .map_err(TantivyViewerError::TantivyError)?
+        .into_iter()
+        .find(|x| x.id() == segment_id);
+
+    let mut fields = Vec::new();
+    for (idx, field_entry) in schema.fields().iter().enumerate() {
+        let field = Field(idx as u32);
+        let num_terms = if field_entry.field_type().get_index_record_option().is_some() {
+            Some(segment.inverted_index(field).terms().num_terms())
+        } else {
+            None
+        };
+        let mut stats = SegmentFieldStats {
+            name: field_entry.name().to_string(),
+            num_terms,
+            termdict: field_usage(usage.termdict(), field),
+            postings: field_usage(usage.postings(), field),
+            positions: field_usage(usage.positions(), field),
+            fast_fields: field_usage(usage.fast_fields(), field),
+            fieldnorms: field_usage(usage.fieldnorms(), field),
+            total: 0,
+        };
+        stats.total = stats.termdict + stats.postings + stats.positions + stats.fast_fields + stats.fieldnorms;
+        fields.push(stats);
+    }
+    fields.sort_by(|x, y| x.name.cmp(&y.name));
+
+    Ok(SegmentDetail {
+        summary: segment_summary(segment, &usage, meta.as_ref()),
+        store: usage.store().total(),
+        deletes: usage.deletes(),
+        fields,
+    })
+}
+
+#[derive(Deserialize)]
+pub struct SegmentQuery {
+    segment: String,
+}
+
+pub(crate) fn handle_segment(req: (HttpRequest<State>, Query<SegmentQuery>)) -> Result<HttpResponse, Error> {
+    let (req, params) = req;
+    let state = req.state();
+    let segment_id = match find_segment(&state.index, &params.segment).map_err(TantivyViewerError::TantivyError)? {
+        SegmentMatch::Found(segment_id) => segment_id,
+        SegmentMatch::Ambiguous(candidates) => return Ok(disambiguation_page(&req, "segment", &params.segment, candidates)?),
+        SegmentMatch::NotFound => return Err(TantivyViewerError::SegmentNotFoundError.into()),
+    };
+    let data = segment_detail(&state.index, segment_id)?;
+    Ok(state.render_template("segment", &data)?)
+}
+
+#[derive(Serialize)]
+pub struct DeletedDocsData {
+    segment: String,
+    num_deleted_docs: u32,
+    identifying_fields: Vec<String>,
+    docs: Vec<DocMatch>,
+    truncated: bool,
+}
+
+/// Lists the docs marked in a segment's delete bitset, with their identifying fields
+/// reconstructed from what is still left in the segment.
+pub(crate) fn deleted_docs<R>(index: &Index, segment_id: SegmentId, identifying_fields: Vec<String>, limit: usize, reconstruct_field: R) -> Result<DeletedDocsData, Error>
+    where R: Fn(&str, &HashMap<SegmentId, Vec<DocId>>) -> Result<HashMap<SegmentId, Vec<(DocId, Vec<Option<TantivyValue>>)>>, Error> {
+    let searcher = index.searcher();
+    let segment = searcher.segment_readers().iter()
+        .find(|x| x.segment_id() == segment_id)
+        .ok_or(TantivyViewerError::SegmentNotFoundError)?;
+
+    let deleted = (0..segment.max_doc())
+        .filter(|&doc| segment.is_deleted(doc))
+        .map(|doc| (segment_id, doc))
+        .take(limit + 1)
+        .collect::<Vec<_>>();
+    let truncated = deleted.len() > limit;
+
+    Ok(DeletedDocsData {
+        segment: segment_id.uuid_string(),
+        num_deleted_docs: segment.num_deleted_docs(),
+        docs: describe_matches(&identifying_fields, deleted.into_iter().take(limit).collect(), reconstruct_field)?,
+        identifying_fields,
+        truncated,
+    })
+}
+
+#[derive(Deserialize)]
+pub struct DeletedDocsQuery {
+    segment: String,
+    limit: Option<usize>,
+}
+
+pub(crate) fn handle_deleted_docs(req: (HttpRequest<State>, Query<DeletedDocsQuery>)) -> Result<HttpResponse, Error> {
+    let (req, params) = req;
+    let state = req.state();
+    let segment_id = match find_segment(&state.index, &params.segment).map_err(TantivyViewerError::TantivyError)? {
+        SegmentMatch::Found(segment_id) => segment_id,
+        SegmentMatch::Ambiguous(candidates) => return Ok(disambiguation_page(&req, "segment", &params.segment, candidates)?),
+        SegmentMatch::NotFound => return Err(TantivyViewerError::SegmentNotFoundError.into()),
+    };
+    let identifying_fields = get_identifying_fields(&req);
+    let data = deleted_docs(&state.index, segment_id, identifying_fields, params.limit.unwrap_or(1000), |field, docs| state.reconstruct(field, docs))?;
+    Ok(state.render_template("deleted_docs", &data)?)
+}
+
+pub fn run_segments(index: &Index, format: OutputFormat) -> Result<(), Error> {
+    let data = segments(index)?;
+    match format {
+        OutputFormat::Json => print_json(&data)?,
+        OutputFormat::Table => {
+            let rows = data.segments.iter()
+                .map(|x| vec![
+                    x.id.clone(),
+                    x.max_doc.to_string(),
+                    x.num_docs.to_string(),
+                    x.num_deleted_docs.to_string(),
+                    x.deleted_ratio.clone(),
+                    x.delete_opstamp.map(|x| x.to_string()).unwrap_or("-".to_string()),
+                    x.total_bytes.to_string(),
+                ])
+                .collect::<Vec<_>>();
+            print_table(&["segment", "max doc", "live docs", "deleted docs", "deleted", "delete opstamp", "bytes"], &rows);
+        }
+    }
+    Ok(())
+}
+
+pub fn run_segment(index: &Index, segment: &str, format: OutputFormat) -> Result<(), Error> {
+    let data = segment_detail(index, resolve_segment(index, segment)?)?;
+    match format {
+        OutputFormat::Json => print_json(&data)?,
+        OutputFormat::Table => {
+            println!("Segment {}: {} live docs, {} deleted docs of {} ({}), {} bytes",
+                data.summary.id,
+                data.summary.num_docs,
+                data.summary.num_deleted_docs,
+                data.summary.max_doc,
+                data.summary.deleted_ratio,
+                data.summary.total_bytes);
+            println!("Doc store: {} bytes, deletes: {} bytes", data.store, data.deletes);
+            let rows = data.fields.iter()
+                .map(|x| vec![
+                    x.name.clone(),
+                    x.num_terms.map(|x| x.to_string()).unwrap_or("-".to_string()),
+                    x.termdict.to_string(),
+                    x.postings.to_string(),
+                    x.positions.to_string(),
+                    x.fast_fields.to_string(),
+                    x.fieldnorms.to_string(),
+                    x.total.to_string(),
+                ])
+                .collect::<Vec<_>>();
+            print_table(&["field", "terms", "termdict", "postings", "positions", "fast fields", "fieldnorms", "total"], &rows);
+        }
+    }
+    Ok(())
+}
+
+pub fn run_deleted_docs(index: &Index, segment: &str, identifying_fields: Vec<String>, limit: usize, format: OutputFormat) -> Result<(), Error> {
+    let segment_id = resolve_segment(index, segment)?;
+    let data = deleted_docs(index, segment_id, identifying_fields, limit, |field, docs| reconstruct(index, field, docs))?;
+    match format {
+        OutputFormat::Json => print_json(&data)?,
+        OutputFormat::Table => {
+            let mut headers = vec!["doc"];
+            headers.extend(data.identifying_fields.iter().map(|x| &x[..]));
+            let rows = data.docs.iter()
+                .map(|x| {
+                    let mut row = vec![x.doc().to_string()];
+                    row.extend(x.identifying_values().iter().map(|x| x.trim_right().to_string()));
+                    row
+                })
+                .collect::<Vec<_>>();
+            print_table(&headers, &rows);
+            if data.truncated {
+                println!("[...Truncated after {} of {} deleted docs...]", limit, data.num_deleted_docs);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tantivy::Document;
+    use tantivy::Term;
+    use tantivy::merge_policy::NoMergePolicy;
+    use tantivy::schema::SchemaBuilder;
+    use tantivy::schema::STORED;
+    use tantivy::schema::STRING;
+
+    /// Builds an index with one segment per slice of ids, then deletes `deleted`.
+    fn test_index(segments: &[&[&str]], deleted: &[&str]) -> Index {
+        let mut schema_builder = SchemaBuilder::default();
+        let id = schema_builder.add_text_field("id", STRING | STORED);
+        let index = Index::create_in_ram(schema_builder.build());
+        let mut writer = index.writer_with_num_threads(1, 40_000_000).unwrap();
+        writer.set_merge_policy(Box::new(NoMergePolicy));
+        for ids in segments {
+            for value in ids.iter() {
+                let mut document = Document::default();
+                document.add_text(id, value);
+                writer.add_document(document);
+            }
+            writer.commit().unwrap();
+        }
+        if !deleted.is_empty() {
+            for value in deleted {
+                writer.delete_term(Term::from_field_text(id, value));
+            }
+            writer.commit().unwrap();
+        }
+        writer.wait_merging_threads().unwrap();
+        index.load_searchers().unwrap();
+        index
+    }
+
+    #[test]
+    fn test_deleted_ratio() {
+        assert_eq!(deleted_ratio(0, 0), "0.0%");
+        assert_eq!(deleted_ratio(1, 3), "33.3%");
+        assert_eq!(deleted_ratio(4, 4), "100.0%");
+    }
+
+    #[test]
+    fn test_segments_counts_live_and_deleted_docs() {
+        let index = test_index(&[&["a", "b", "c"], &["d"]], &["b"]);
+        let mut data = segments(&index).unwrap().segments;
+        data.sort_by_key(|x| x.max_doc);
+        let counts = data.iter().map(|x| (x.max_doc, x.num_docs, x.num_deleted_docs)).collect::<Vec<_>>();
+        assert_eq!(counts, vec![(1, 1, 0), (3, 2, 1)]);
+        assert_eq!(data[1].deleted_ratio, "33.3%");
+        assert!(data.iter().all(|x| !x.files.is_empty()));
+    }
+
+    #[test]
+    fn test_find_segment() {
+        let index = test_index(&[&["a"], &["b"]], &[]);
+        let segment_ids = index.searchable_segment_ids().unwrap();
+        assert_eq!(segment_ids.len(), 2);
+
+        let full = segment_ids[0].uuid_string();
+        match find_segment(&index, &full).unwrap() {
+            SegmentMatch::Found(segment_id) => assert_eq!(segment_id, segment_ids[0]),
+            _ => panic!("a full id should match exactly one segment"),
+        }
+        match find_segment(&index, &full[..20]).unwrap() {
+            SegmentMatch::Found(segment_id) => assert_eq!(segment_id, segment_ids[0]),
+            _ => panic!("a long prefix should match exactly one segment"),
+        }
+        match find_segment(&index, "").unwrap() {
+            SegmentMatch::Ambiguous(candidates) => assert_eq!(candidates.len(), 2),
+            _ => panic!("an empty prefix should match every segment"),
+        }
+        match find_segment(&index, "not-a-segment").unwrap() {
+            SegmentMatch::NotFound => {},
+            _ => panic!("a prefix no segment has should match nothing"),
+        }
+        assert!(resolve_segment(&index, "").is_err());
+    }
+
+    #[test]
+    fn test_deleted_docs() {
+        let index = test_index(&[&["a", "b", "c", "d"]], &["b", "d"]);
+        let segment_id = index.searchable_segment_ids().unwrap()[0];
+        let reconstruct_field = |field: &str, docs: &HashMap<SegmentId, Vec<DocId>>| reconstruct(&index, field, docs);
+
+        let data = deleted_docs(&index, segment_id, vec!["id".to_string()], 10, &reconstruct_field).unwrap();
+        assert_eq!(data.num_deleted_docs, 2);
+        assert!(!data.truncated);
+        let docs = data.docs.iter()
+            .map(|x| (x.doc(), x.identifying_values()[0].trim_right().to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(docs, vec![(1, "b".to_string()), (3, "d".to_string())]);
+
+        let data = deleted_docs(&index, segment_id, Vec::new(), 1, &reconstruct_field).unwrap();
+        assert_eq!(data.docs.len(), 1);
+        assert!(data.truncated);
+    }
+
+    #[test]
+    fn test_doc_neighbours_skip_deleted_docs() {
+        let index = test_index(&[&["a", "b", "c", "d"], &["e"]], &["a", "c"]);
+        let searcher = index.searcher();
+        let readers = searcher.segment_readers();
+        let big = readers.iter().position(|x| x.max_doc() == 4).unwrap();
+        let big_id = readers[big].segment_id();
+        let small_id = readers[1 - big].segment_id();
+
+        let neighbours = doc_neighbours(&index, big_id, 1).unwrap();
+        assert_eq!(neighbours.first, Some((big_id, 1)));
+        assert_eq!(neighbours.prev, None);
+        assert_eq!(neighbours.next, Some((big_id, 3)));
+        assert_eq!(neighbours.last, Some((big_id, 3)));
+        assert_eq!(neighbours.next_segment, if big == 0 { Some((small_id, 0)) } else { None });
+
+        let neighbours = doc_neighbours(&index, big_id, 3).unwrap();
+        assert_eq!(neighbours.prev, Some((big_id, 1)));
+        assert_eq!(neighbours.next, None);
+    }
+
+    #[test]
+    fn test_segment_for_ordinal() {
+        let index = test_index(&[&["a", "b"], &["c"]], &[]);
+        let searcher = index.searcher();
+        let readers = searcher.segment_readers();
+        let mut expected = Vec::new();
+        for segment in readers {
+            for doc in 0..segment.max_doc() {
+                expected.push(Some((segment.segment_id(), doc)));
+            }
+        }
+        expected.push(None);
+        let actual = (0..4).map(|ordinal| segment_for_ordinal(&index, ordinal)).collect::<Vec<_>>();
+        assert_eq!(actual, expected);
+    }
+}
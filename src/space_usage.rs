@@ -3,6 +3,11 @@ use std::collections::HashMap;
 use tantivy::space_usage::PerFieldSpaceUsage;
 use tantivy::schema::Schema;
 use tantivy::space_usage::ByteCount;
+use failure::Error;
+use output::print_json;
+use output::print_table;
+use output::OutputFormat;
+use top_terms::unique_terms;
 
 fn add_fields(schema: &Schema, accum: &mut HashMap<String, usize>, usage: &PerFieldSpaceUsage) {
     for (field, usage) in usage.fields() {
@@ -56,4 +61,104 @@ pub fn space_usage(index: &Index) -> SpaceUsage {
         concepts,
         total,
     }
-}
\ No newline at end of file
+}
+
+/// Per-field figures used when comparing two indexes.
+pub struct FieldStats {
+    pub total: usize,
+    pub components: HashMap<String, usize>,
+    pub unique_terms: Option<u64>,
+}
+
+/// Space usage together with the document and term counts that explain it.
+pub struct IndexStats {
+    pub num_segments: usize,
+    pub num_docs: u64,
+    pub total: usize,
+    pub components: HashMap<String, usize>,
+    pub fields: HashMap<String, FieldStats>,
+}
+
+fn add_field_components(schema: &Schema, accum: &mut HashMap<String, FieldStats>, component: &str, usage: &PerFieldSpaceUsage) {
+    for (field, usage) in usage.fields() {
+        let name = schema.get_field_name(*field).to_string();
+        let stats = accum.entry(name).or_insert_with(|| FieldStats {
+            total: 0,
+            components: HashMap::new(),
+            unique_terms: None,
+        });
+        stats.total += usage.total();
+        *stats.components.entry(component.to_string()).or_insert(0) += usage.total();
+    }
+}
+
+pub fn index_stats(index: &Index) -> Result<IndexStats, Error> {
+    let schema = index.schema();
+    let searcher = index.searcher();
+    let space_usage = searcher.space_usage();
+
+    let mut components = HashMap::new();
+    let mut fields = HashMap::new();
+
+    for segment in space_usage.segments() {
+        add_field_components(&schema, &mut fields, "termdict", segment.termdict());
+        add_concept(&mut components, "termdict", segment.termdict().total());
+
+        add_field_components(&schema, &mut fields, "postings", segment.postings());
+        add_concept(&mut components, "postings", segment.postings().total());
+
+        add_field_components(&schema, &mut fields, "positions", segment.positions());
+        add_concept(&mut components, "positions", segment.positions().total());
+
+        add_field_components(&schema, &mut fields, "fast_fields", segment.fast_fields());
+        add_concept(&mut components, "fast_fields", segment.fast_fields().total());
+
+        add_field_components(&schema, &mut fields, "fieldnorms", segment.fieldnorms());
+        add_concept(&mut components, "fieldnorms", segment.fieldnorms().total());
+
+        add_concept(&mut components, "deletes", segment.deletes());
+        add_concept(&mut components, "store", segment.store().total());
+    }
+
+    for field_entry in schema.fields() {
+        if field_entry.field_type().get_index_record_option().is_none() {
+            continue;
+        }
+        let name = field_entry.name().to_string();
+        let count = unique_terms(index, &name)?;
+        fields.entry(name).or_insert_with(|| FieldStats {
+            total: 0,
+            components: HashMap::new(),
+            unique_terms: None,
+        }).unique_terms = Some(count);
+    }
+
+    Ok(IndexStats {
+        num_segments: searcher.segment_readers().len(),
+        num_docs: searcher.num_docs(),
+        total: space_usage.total(),
+        components,
+        fields,
+    })
+}
+
+fn usage_rows(usage: &HashMap<String, usize>) -> Vec<Vec<String>> {
+    let mut entries = usage.iter().collect::<Vec<_>>();
+    entries.sort_by(|x, y| y.1.cmp(x.1).then_with(|| x.0.cmp(y.0)));
+    entries.into_iter().map(|(name, &bytes)| vec![name.clone(), bytes.to_string()]).collect()
+}
+
+pub fn run_space_usage(index: &Index, format: OutputFormat) -> Result<(), Error> {
+    let usage = space_usage(index);
+    match format {
+        OutputFormat::Json => print_json(&usage)?,
+        OutputFormat::Table => {
+            print_table(&["component", "bytes"], &usage_rows(&usage.concepts));
+            println!();
+            print_table(&["field", "bytes"], &usage_rows(&usage.fields));
+            println!();
+            println!("Total: {} bytes", usage.total);
+        }
+    }
+    Ok(())
+}
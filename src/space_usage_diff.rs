@@ -0,0 +1,204 @@
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use failure::Error;
+use tantivy::Index;
+
+use output::print_json;
+use output::print_table;
+use output::OutputFormat;
+use space_usage::index_stats;
+use space_usage::IndexStats;
+use State;
+use TantivyViewerError;
+
+#[derive(Serialize)]
+pub struct DiffRow {
+    name: String,
+    baseline: i64,
+    candidate: i64,
+    delta: i64,
+    change: String,
+}
+
+impl DiffRow {
+    fn new(name: &str, baseline: i64, candidate: i64) -> DiffRow {
+        DiffRow {
+            name: name.to_string(),
+            baseline,
+            candidate,
+            delta: candidate - baseline,
+            change: percent_change(baseline, candidate),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct FieldDiff {
+    name: String,
+    total: DiffRow,
+    unique_terms: Option<DiffRow>,
+    components: Vec<DiffRow>,
+}
+
+#[derive(Serialize)]
+pub struct SpaceUsageDiff {
+    counts: Vec<DiffRow>,
+    total: DiffRow,
+    components: Vec<DiffRow>,
+    fields: Vec<FieldDiff>,
+}
+
+fn percent_change(baseline: i64, candidate: i64) -> String {
+    if baseline == 0 {
+        if candidate == 0 {
+            "0.0%".to_string()
+        } else {
+            "new".to_string()
+        }
+    } else {
+        format!("{:+.1}%", (candidate - baseline) as f64 * 100.0 / baseline as f64)
+    }
+}
+
+fn diff_maps(baseline: &HashMap<String, usize>, candidate: &HashMap<String, usize>) -> Vec<DiffRow> {
+    let keys = baseline.keys().chain(candidate.keys()).collect::<BTreeSet<_>>();
+    keys.into_iter()
+        .map(|key| DiffRow::new(
+            key,
+            baseline.get(key).cloned().unwrap_or(0) as i64,
+            candidate.get(key).cloned().unwrap_or(0) as i64,
+        ))
+        .collect()
+}
+
+pub fn diff_stats(baseline: &IndexStats, candidate: &IndexStats) -> SpaceUsageDiff {
+    let counts = vec![
+        DiffRow::new("segments", baseline.num_segments as i64, candidate.num_segments as i64),
+        DiffRow::new("docs", baseline.num_docs as i64, candidate.num_docs as i64),
+    ];
+
+    let empty = HashMap::new();
+    let field_names = baseline.fields.keys().chain(candidate.fields.keys()).collect::<BTreeSet<_>>();
+    let fields = field_names.into_iter()
+        .map(|name| {
+            let baseline_field = baseline.fields.get(name);
+            let candidate_field = candidate.fields.get(name);
+            let unique_terms = match (baseline_field.and_then(|x| x.unique_terms), candidate_field.and_then(|x| x.unique_terms)) {
+                (None, None) => None,
+                (b, c) => Some(DiffRow::new("unique terms", b.unwrap_or(0) as i64, c.unwrap_or(0) as i64)),
+            };
+            FieldDiff {
+                name: name.clone(),
+                total: DiffRow::new(
+                    "total",
+                    baseline_field.map(|x| x.total).unwrap_or(0) as i64,
+                    candidate_field.map(|x| x.total).unwrap_or(0) as i64,
+                ),
+                unique_terms,
+                components: diff_maps(
+                    baseline_field.map(|x| &x.components).unwrap_or(&empty),
+                    candidate_field.map(|x| &x.components).unwrap_or(&empty),
+                ),
+            }
+        })
+        .collect();
+
+    SpaceUsageDiff {
+        counts,
+        total: DiffRow::new("total", baseline.total as i64, candidate.total as i64),
+        components: diff_maps(&baseline.components, &candidate.components),
+        fields,
+    }
+}
+
+pub fn space_usage_diff(baseline: &Index, candidate: &Index) -> Result<SpaceUsageDiff, Error> {
+    Ok(diff_stats(&index_stats(baseline)?, &index_stats(candidate)?))
+}
+
+pub(crate) fn handle_space_usage_diff(req: HttpRequest<State>) -> Result<HttpResponse, Error> {
+    let state = req.state();
+    let candidate = state.candidate.as_ref().ok_or(TantivyViewerError::NoCandidateIndexError)?;
+    let diff = space_usage_diff(&state.index, candidate)?;
+    Ok(state.render_template("space_usage_diff", &diff)?)
+}
+
+fn diff_row(row: &DiffRow) -> Vec<String> {
+    vec![row.name.clone(), row.baseline.to_string(), row.candidate.to_string(), row.delta.to_string(), row.change.clone()]
+}
+
+pub fn run_space_usage_diff(baseline: &Index, candidate: Option<&Index>, format: OutputFormat) -> Result<(), Error> {
+    let candidate = candidate.ok_or(TantivyViewerError::NoCandidateIndexError)?;
+    let diff = space_usage_diff(baseline, candidate)?;
+    match format {
+        OutputFormat::Json => print_json(&diff)?,
+        OutputFormat::Table => {
+            let headers = ["", "baseline", "candidate", "delta", "change"];
+            let mut rows = diff.counts.iter().map(diff_row).collect::<Vec<_>>();
+            rows.extend(diff.components.iter().map(diff_row));
+            rows.push(diff_row(&diff.total));
+            print_table(&headers, &rows);
+            for field in diff.fields.iter() {
+                println!();
+                println!("{}", field.name);
+                let mut rows = field.components.iter().map(diff_row).collect::<Vec<_>>();
+                rows.extend(field.unique_terms.iter().map(diff_row));
+                rows.push(diff_row(&field.total));
+                print_table(&headers, &rows);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use space_usage::FieldStats;
+
+    fn stats(num_docs: u64, total: usize, components: &[(&str, usize)], fields: Vec<(&str, FieldStats)>) -> IndexStats {
+        IndexStats {
+            num_segments: 1,
+            num_docs,
+            total,
+            components: components.iter().map(|&(name, bytes)| (name.to_string(), bytes)).collect(),
+            fields: fields.into_iter().map(|(name, stats)| (name.to_string(), stats)).collect(),
+        }
+    }
+
+    fn field(total: usize, unique_terms: Option<u64>) -> FieldStats {
+        FieldStats {
+            total,
+            components: vec![("postings".to_string(), total)].into_iter().collect(),
+            unique_terms,
+        }
+    }
+
+    #[test]
+    fn test_percent_change() {
+        assert_eq!(percent_change(0, 0), "0.0%");
+        assert_eq!(percent_change(0, 10), "new");
+        assert_eq!(percent_change(200, 250), "+25.0%");
+        assert_eq!(percent_change(200, 100), "-50.0%");
+    }
+
+    #[test]
+    fn test_diff_stats_covers_both_sides() {
+        let baseline = stats(10, 100, &[("store", 60), ("postings", 40)], vec![("title", field(40, Some(5)))]);
+        let candidate = stats(12, 90, &[("store", 60), ("positions", 30)], vec![("body", field(30, None))]);
+        let diff = diff_stats(&baseline, &candidate);
+
+        assert_eq!(diff.total.delta, -10);
+        assert_eq!(diff.counts[1].name, "docs");
+        assert_eq!(diff.counts[1].delta, 2);
+
+        let components = diff.components.iter().map(|x| (x.name.as_str(), x.baseline, x.candidate)).collect::<Vec<_>>();
+        assert_eq!(components, vec![("positions", 0, 30), ("postings", 40, 0), ("store", 60, 60)]);
+
+        let fields = diff.fields.iter().map(|x| (x.name.as_str(), x.total.baseline, x.total.candidate)).collect::<Vec<_>>();
+        assert_eq!(fields, vec![("body", 0, 30), ("title", 40, 0)]);
+        assert!(diff.fields[0].unique_terms.is_none());
+        assert_eq!(diff.fields[1].unique_terms.as_ref().map(|x| (x.baseline, x.candidate)), Some((5, 0)));
+    }
+}
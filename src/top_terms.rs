@@ -3,12 +3,15 @@ use failure::Error;
 use tantivy::schema::Type;
 use tantivy::Term;
 use tantivy::schema::Field;
+use tantivy::schema::Value;
 use fst::Automaton;
 use tantivy::termdict::TermStreamer;
 use std::cmp::Ordering;
 use std::fmt;
 use std::str;
 use tantivy::Index;
+use tantivy::Searcher;
+use tantivy::postings::TermInfo;
 use std::collections::BinaryHeap;
 use std::collections::binary_heap::PeekMut;
 
@@ -66,6 +69,15 @@ impl fmt::Display for TantivyValue {
     }
 }
 
+/// Facet terms hold the steps of the path separated by a zero byte, without the leading
+/// slash. The root facet is indexed as an empty term.
+fn facet_path(key: &[u8]) -> String {
+    let steps = key.split(|&b| b == 0u8)
+        .map(|step| String::from_utf8_lossy(step).into_owned())
+        .collect::<Vec<_>>();
+    format!("/{}", steps.join("/"))
+}
+
 impl TantivyValue {
     pub fn from_term(key: &[u8], ty: Type) -> TantivyValue {
         let term = Term::from_field_text(Field(0), unsafe { str::from_utf8_unchecked(key) });
@@ -73,13 +85,25 @@ impl TantivyValue {
             Type::Str => TantivyValue::Text(term.text().to_string()),
             Type::U64 => TantivyValue::U64(term.get_u64()),
             Type::I64 => TantivyValue::I64(term.get_i64()),
-            Type::HierarchicalFacet => unimplemented!(),
+            Type::HierarchicalFacet => TantivyValue::Text(facet_path(key)),
             Type::Bytes => unimplemented!(),
         }
     }
+
+    /// Converts a stored value, returning `None` for facets which have no `TantivyValue` counterpart.
+    pub fn from_value(value: &Value) -> Option<TantivyValue> {
+        match *value {
+            Value::Str(ref val) => Some(TantivyValue::Text(val.clone())),
+            Value::U64(val) => Some(TantivyValue::U64(val)),
+            Value::I64(val) => Some(TantivyValue::I64(val)),
+            Value::Bytes(ref val) => Some(TantivyValue::Bytes(val.clone())),
+            Value::Facet(_) => None,
+        }
+    }
 }
 
 struct StreamerWrapper<'a, A: Automaton> {
+    segment_ord: usize,
     streamer: TermStreamer<'a, A>,
 }
 
@@ -103,16 +127,17 @@ impl<'a, A: Automaton> PartialEq for StreamerWrapper<'a, A> {
 
 impl<'a, A: Automaton> Eq for StreamerWrapper<'a, A> {}
 
-pub fn top_terms(index: &Index, field: &str, k: usize) -> Result<TopTerms, Error> {
-    let searcher = index.searcher();
-    let field = index.schema().get_field(field).ok_or(err_msg("Sorry, that field does not exist!"))?;
-    let value_type = index.schema().get_field_entry(field).field_type().value_type();
+/// Walks the merged term dictionaries of every segment in `searcher` for `field` in sorted
+/// order, calling `f` once per distinct term with the segment ordinals and term infos
+/// of the segments containing it.
+pub fn for_each_merged_term_info<F: FnMut(&[u8], &[(usize, TermInfo)])>(searcher: &Searcher, field: Field, mut f: F) {
     let indexes = searcher.segment_readers().iter().map(|x| x.inverted_index(field)).collect::<Vec<_>>();
 
-    let mut streams = indexes.iter().filter_map(|x| {
+    let mut streams = indexes.iter().enumerate().filter_map(|(segment_ord, x)| {
         let mut stream = x.terms().stream();
         if stream.advance() {
             Some(StreamerWrapper {
+                segment_ord,
                 streamer: stream,
             })
         } else {
@@ -120,15 +145,14 @@ pub fn top_terms(index: &Index, field: &str, k: usize) -> Result<TopTerms, Error
         }
     }).collect::<BinaryHeap<_>>();
 
-    let mut pq = BinaryHeap::new();
-
+    let mut term_infos = Vec::new();
     while !streams.is_empty() {
         let current_key = streams.peek().unwrap().streamer.key().to_owned();
-        let mut count = 0;
+        term_infos.clear();
 
         while let Some(mut head) = streams.peek_mut() {
             if head.streamer.key() == &current_key[..] {
-                count += head.streamer.value().doc_freq as i64;
+                term_infos.push((head.segment_ord, head.streamer.value().clone()));
                 if !head.streamer.advance() {
                     PeekMut::pop(head);
                 }
@@ -137,12 +161,40 @@ pub fn top_terms(index: &Index, field: &str, k: usize) -> Result<TopTerms, Error
             }
         }
 
+        f(&current_key[..], &term_infos[..]);
+    }
+}
+
+/// Walks the merged term dictionaries of every segment for `field` in sorted order,
+/// calling `f` once per distinct term with the summed doc frequency.
+pub fn for_each_merged_term<F: FnMut(&[u8], i64)>(index: &Index, field: Field, mut f: F) {
+    let searcher = index.searcher();
+    for_each_merged_term_info(&*searcher, field, |key, term_infos| {
+        f(key, term_infos.iter().map(|&(_, ref term_info)| term_info.doc_freq as i64).sum());
+    });
+}
+
+/// Counts the distinct terms of `field` across all segments.
+pub fn unique_terms(index: &Index, field: &str) -> Result<u64, Error> {
+    let field = index.schema().get_field(field).ok_or(err_msg("Sorry, that field does not exist!"))?;
+    let mut count = 0;
+    for_each_merged_term(index, field, |_key, _doc_freq| count += 1);
+    Ok(count)
+}
+
+pub fn top_terms(index: &Index, field: &str, k: usize) -> Result<TopTerms, Error> {
+    let field = index.schema().get_field(field).ok_or(err_msg("Sorry, that field does not exist!"))?;
+    let value_type = index.schema().get_field_entry(field).field_type().value_type();
+
+    let mut pq = BinaryHeap::new();
+
+    for_each_merged_term(index, field, |key, count| {
         if pq.len() < k {
-            pq.push(TermCount { count: -count, term: TantivyValue::from_term(&current_key[..], value_type) });
+            pq.push(TermCount { count: -count, term: TantivyValue::from_term(key, value_type) });
         } else if pq.peek().unwrap().count > -count {
-            *pq.peek_mut().unwrap() = TermCount { count: -count, term: TantivyValue::from_term(&current_key[..], value_type) };
+            *pq.peek_mut().unwrap() = TermCount { count: -count, term: TantivyValue::from_term(key, value_type) };
         }
-    }
+    });
 
     let mut vec = Vec::new();
     while let Some(mut termcount) = pq.pop() {
@@ -155,3 +207,15 @@ pub fn top_terms(index: &Index, field: &str, k: usize) -> Result<TopTerms, Error
         terms: vec,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_facet_path() {
+        assert_eq!(facet_path(b""), "/");
+        assert_eq!(facet_path(b"a"), "/a");
+        assert_eq!(facet_path(b"a\0b c\0d"), "/a/b c/d");
+    }
+}
@@ -0,0 +1,415 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::mem;
+use std::sync::Arc;
+use std::sync::Mutex;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use failure::err_msg;
+use failure::Error;
+use tantivy::DocId;
+use tantivy::DocSet;
+use tantivy::Index;
+use tantivy::SegmentId;
+use tantivy::SegmentReader;
+use tantivy::schema::Field;
+use tantivy::schema::IndexRecordOption;
+use tantivy::schema::Type;
+
+use reconstruct::reconstruct;
+use reconstruct::reconstruct_doc;
+use top_terms::TantivyValue;
+use State;
+
+/// Every doc of a segment with the values of one field, by position.
+pub(crate) struct UninvertedField {
+    pub(crate) docs: Vec<Vec<Option<TantivyValue>>>,
+    num_bytes: usize,
+}
+
+fn value_bytes(value: &Option<TantivyValue>) -> usize {
+    let heap_bytes = match *value {
+        Some(TantivyValue::Text(ref text)) => text.capacity(),
+        Some(TantivyValue::Bytes(ref bytes)) => bytes.capacity(),
+        _ => 0,
+    };
+    mem::size_of::<Option<TantivyValue>>() + heap_bytes
+}
+
+/// Streams the whole term dictionary of `field` once, filling in every doc of the segment.
+///
+/// Gives up and returns `None` as soon as the values built so far take more than `max_bytes`,
+/// so a field too large for the budget never has to be held in memory whole.
+pub(crate) fn uninvert(segment: &SegmentReader, field: Field, value_type: Type, options: IndexRecordOption, max_bytes: usize) -> Option<UninvertedField> {
+    let mut docs = vec![Vec::new(); segment.max_doc() as usize];
+    let inverted_index = segment.inverted_index(field);
+    let mut term_stream = inverted_index.terms().stream();
+    let mut positions_buf = Vec::new();
+    let mut num_bytes = docs.len() * mem::size_of::<Vec<Option<TantivyValue>>>();
+
+    while term_stream.advance() {
+        // Only text terms own heap memory once turned into values.
+        let term_heap_bytes = match value_type {
+            Type::Str => term_stream.key().len(),
+            _ => 0,
+        };
+        let mut postings = inverted_index.read_postings_from_terminfo(term_stream.value(), options);
+        while postings.advance() {
+            let values = &mut docs[postings.doc() as usize];
+            let previous_len = values.len();
+            reconstruct_doc(values, &mut positions_buf, &mut postings, term_stream.key(), value_type);
+            num_bytes += (values.len() - previous_len) * mem::size_of::<Option<TantivyValue>>()
+                + postings.term_freq() as usize * term_heap_bytes;
+            if num_bytes > max_bytes {
+                return None;
+            }
+        }
+    }
+
+    let num_bytes = docs.iter()
+        .map(|values| mem::size_of::<Vec<Option<TantivyValue>>>() + values.iter().map(value_bytes).sum::<usize>())
+        .sum();
+    Some(UninvertedField { docs, num_bytes })
+}
+
+/// Which entry to drop when the cache is full.
+#[derive(Clone, Copy, Debug)]
+pub enum EvictionPolicy {
+    /// Least recently used first.
+    Lru,
+    /// Fewest hits first, then least recently used.
+    Lfu,
+    /// Oldest first, however often it is used.
+    Fifo,
+}
+
+impl EvictionPolicy {
+    pub fn parse(policy: Option<&str>) -> Result<EvictionPolicy, Error> {
+        match policy {
+            None | Some("lru") => Ok(EvictionPolicy::Lru),
+            Some("lfu") => Ok(EvictionPolicy::Lfu),
+            Some("fifo") => Ok(EvictionPolicy::Fifo),
+            Some(other) => Err(err_msg(format!("Unknown eviction policy '{}'. Expected lru, lfu or fifo", other))),
+        }
+    }
+
+    fn eviction_key(&self, entry: &CacheEntry) -> (u64, u64) {
+        match *self {
+            EvictionPolicy::Lru => (entry.last_used, 0),
+            EvictionPolicy::Lfu => (entry.hits, entry.last_used),
+            EvictionPolicy::Fifo => (entry.inserted, 0),
+        }
+    }
+}
+
+struct CacheEntry {
+    field: Arc<UninvertedField>,
+    inserted: u64,
+    last_used: u64,
+    hits: u64,
+}
+
+#[derive(Default)]
+struct CacheInner {
+    entries: HashMap<(SegmentId, Field), CacheEntry>,
+    /// Fields found to be larger than the whole cache, which are never uninverted again.
+    oversize: HashSet<(SegmentId, Field)>,
+    tick: u64,
+    used_bytes: usize,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+    bypassed: u64,
+}
+
+/// Uninverted indexed fields, built lazily per segment and field on first use and shared
+/// across requests.
+///
+/// Segments never change once written, so entries stay valid until they are evicted.
+/// When over `max_bytes`, entries are dropped in the order given by `policy`. Fields that
+/// can't fit in `max_bytes` at all are left to `reconstruct::reconstruct` instead.
+pub struct UninvertedCache {
+    max_bytes: usize,
+    policy: EvictionPolicy,
+    inner: Mutex<CacheInner>,
+}
+
+impl UninvertedCache {
+    pub fn new(max_bytes: usize, policy: EvictionPolicy) -> UninvertedCache {
+        UninvertedCache {
+            max_bytes,
+            policy,
+            inner: Mutex::new(CacheInner::default()),
+        }
+    }
+
+    /// Returns `None` when the field is too large to cache, without uninverting it again
+    /// once that is known.
+    fn get_or_build(&self, segment: &SegmentReader, field: Field, value_type: Type, options: IndexRecordOption) -> Option<Arc<UninvertedField>> {
+        let key = (segment.segment_id(), field);
+        // Every doc takes at least an empty vector, which is enough to rule out some fields
+        // before walking their terms.
+        let min_bytes = segment.max_doc() as usize * mem::size_of::<Vec<Option<TantivyValue>>>();
+        {
+            let mut inner = self.inner.lock().unwrap();
+            if min_bytes > self.max_bytes || inner.oversize.contains(&key) {
+                inner.bypassed += 1;
+                return None;
+            }
+            inner.tick += 1;
+            let tick = inner.tick;
+            let found = match inner.entries.get_mut(&key) {
+                Some(entry) => {
+                    entry.last_used = tick;
+                    entry.hits += 1;
+                    Some(entry.field.clone())
+                }
+                None => None,
+            };
+            match found {
+                Some(field) => {
+                    inner.hits += 1;
+                    return Some(field);
+                }
+                None => inner.misses += 1,
+            }
+        }
+
+        // Built without holding the lock, so other requests aren't blocked behind a large field.
+        let uninverted = uninvert(segment, field, value_type, options, self.max_bytes);
+        let mut inner = self.inner.lock().unwrap();
+        let uninverted = match uninverted {
+            Some(uninverted) if uninverted.num_bytes <= self.max_bytes => Arc::new(uninverted),
+            _ => {
+                inner.oversize.insert(key);
+                inner.bypassed += 1;
+                return None;
+            }
+        };
+
+        let policy = self.policy;
+        while inner.used_bytes + uninverted.num_bytes > self.max_bytes {
+            let oldest = inner.entries.iter()
+                .min_by_key(|&(_, entry)| policy.eviction_key(entry))
+                .map(|(&key, _)| key);
+            match oldest {
+                Some(oldest) => {
+                    let evicted = inner.entries.remove(&oldest).unwrap();
+                    inner.used_bytes -= evicted.field.num_bytes;
+                    inner.evictions += 1;
+                }
+                None => break,
+            }
+        }
+        let tick = inner.tick;
+        // The miss that built the entry counts as its first use, so under LFU a fresh entry
+        // isn't automatically the next one evicted.
+        if let Some(previous) = inner.entries.insert(key, CacheEntry { field: uninverted.clone(), inserted: tick, last_used: tick, hits: 1 }) {
+            inner.used_bytes -= previous.field.num_bytes;
+        }
+        inner.used_bytes += uninverted.num_bytes;
+        Some(uninverted)
+    }
+
+    /// Same as `reconstruct::reconstruct`, but served from the cache for indexed fields.
+    pub fn reconstruct(&self, index: &Index, field_name: &str, docs: &HashMap<SegmentId, Vec<DocId>>) -> Result<HashMap<SegmentId, Vec<(DocId, Vec<Option<TantivyValue>>)>>, Error> {
+        let schema = index.schema();
+        let field = schema.get_field(field_name).ok_or(err_msg("Field not found"))?;
+        let field_type = schema.get_field_entry(field).field_type();
+        let options = match field_type.get_index_record_option() {
+            Some(options) => options,
+            // Fast fields are already laid out by doc.
+            None => return reconstruct(index, field_name, docs),
+        };
+        let value_type = field_type.value_type();
+        let searcher = index.searcher();
+
+        let mut reconstructed_docs = HashMap::new();
+        for segment in searcher.segment_readers() {
+            if let Some(segment_docs) = docs.get(&segment.segment_id()) {
+                let segment_reconstructed_docs = match self.get_or_build(segment, field, value_type, options) {
+                    Some(uninverted) => segment_docs.iter()
+                        .map(|&doc| (doc, uninverted.docs.get(doc as usize).cloned().unwrap_or_default()))
+                        .collect::<Vec<_>>(),
+                    None => {
+                        let mut oversize_docs = HashMap::new();
+                        oversize_docs.insert(segment.segment_id(), segment_docs.clone());
+                        reconstruct(index, field_name, &oversize_docs)?
+                            .remove(&segment.segment_id())
+                            .unwrap_or_default()
+                    }
+                };
+                reconstructed_docs.insert(segment.segment_id(), segment_reconstructed_docs);
+            }
+        }
+        Ok(reconstructed_docs)
+    }
+
+    pub fn stats(&self, index: &Index) -> CacheStats {
+        let schema = index.schema();
+        let inner = self.inner.lock().unwrap();
+        let mut entries = inner.entries.iter()
+            .map(|(&(segment, field), entry)| CacheEntryStats {
+                segment: segment.uuid_string(),
+                field: schema.get_field_name(field).to_string(),
+                num_bytes: entry.field.num_bytes,
+                hits: entry.hits,
+                last_used: entry.last_used,
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by(|x, y| y.last_used.cmp(&x.last_used));
+        CacheStats {
+            enabled: true,
+            policy: format!("{:?}", self.policy),
+            max_bytes: self.max_bytes,
+            used_bytes: inner.used_bytes,
+            hits: inner.hits,
+            misses: inner.misses,
+            evictions: inner.evictions,
+            bypassed: inner.bypassed,
+            oversize: inner.oversize.len(),
+            entries,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct CacheEntryStats {
+    segment: String,
+    field: String,
+    num_bytes: usize,
+    hits: u64,
+    last_used: u64,
+}
+
+#[derive(Serialize)]
+pub struct CacheStats {
+    enabled: bool,
+    policy: String,
+    max_bytes: usize,
+    used_bytes: usize,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+    bypassed: u64,
+    oversize: usize,
+    entries: Vec<CacheEntryStats>,
+}
+
+impl CacheStats {
+    fn disabled() -> CacheStats {
+        CacheStats {
+            enabled: false,
+            policy: String::new(),
+            max_bytes: 0,
+            used_bytes: 0,
+            hits: 0,
+            misses: 0,
+            evictions: 0,
+            bypassed: 0,
+            oversize: 0,
+            entries: Vec::new(),
+        }
+    }
+}
+
+pub(crate) fn handle_cache(req: HttpRequest<State>) -> Result<HttpResponse, Error> {
+    let state = req.state();
+    let stats = match state.uninverted {
+        Some(ref cache) => cache.stats(&state.index),
+        None => CacheStats::disabled(),
+    };
+    Ok(state.render_template("cache", &stats)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tantivy::Document;
+    use tantivy::schema::SchemaBuilder;
+    use tantivy::schema::STRING;
+
+    /// A one-segment index with three identical fields, so each uninverts to the same size.
+    fn test_index() -> Index {
+        let mut schema_builder = SchemaBuilder::default();
+        let fields = vec![
+            schema_builder.add_text_field("a", STRING),
+            schema_builder.add_text_field("b", STRING),
+            schema_builder.add_text_field("c", STRING),
+        ];
+        let index = Index::create_in_ram(schema_builder.build());
+        let mut writer = index.writer_with_num_threads(1, 40_000_000).unwrap();
+        for i in 0..10 {
+            let mut document = Document::default();
+            for &field in fields.iter() {
+                document.add_text(field, &format!("value{}", i));
+            }
+            writer.add_document(document);
+        }
+        writer.commit().unwrap();
+        index.load_searchers().unwrap();
+        index
+    }
+
+    /// Looks `names` up in order in a cache with room for two fields, and returns the
+    /// fields left in it.
+    fn cached_after(policy: EvictionPolicy, names: &[&str]) -> Vec<String> {
+        let index = test_index();
+        let schema = index.schema();
+        let searcher = index.searcher();
+        let segment = &searcher.segment_readers()[0];
+        let field_bytes = uninvert(segment, Field(0), Type::Str, IndexRecordOption::Basic, usize::MAX).unwrap().num_bytes;
+        let cache = UninvertedCache::new(2 * field_bytes, policy);
+        for name in names {
+            let field = schema.get_field(name).unwrap();
+            assert!(cache.get_or_build(segment, field, Type::Str, IndexRecordOption::Basic).is_some());
+        }
+        let mut fields = cache.stats(&index).entries.into_iter().map(|x| x.field).collect::<Vec<_>>();
+        fields.sort();
+        fields
+    }
+
+    #[test]
+    fn test_parse_policy() {
+        assert!(match EvictionPolicy::parse(None).unwrap() { EvictionPolicy::Lru => true, _ => false });
+        assert!(match EvictionPolicy::parse(Some("lfu")).unwrap() { EvictionPolicy::Lfu => true, _ => false });
+        assert!(match EvictionPolicy::parse(Some("fifo")).unwrap() { EvictionPolicy::Fifo => true, _ => false });
+        assert!(EvictionPolicy::parse(Some("random")).is_err());
+    }
+
+    #[test]
+    fn test_eviction_order() {
+        assert_eq!(cached_after(EvictionPolicy::Lru, &["a", "a", "a", "b", "c"]), vec!["b", "c"]);
+        assert_eq!(cached_after(EvictionPolicy::Lfu, &["a", "a", "a", "b", "c"]), vec!["a", "c"]);
+        assert_eq!(cached_after(EvictionPolicy::Fifo, &["a", "b", "a", "c"]), vec!["b", "c"]);
+        assert_eq!(cached_after(EvictionPolicy::Lru, &["a", "b", "a", "c"]), vec!["a", "c"]);
+    }
+
+    #[test]
+    fn test_uninvert_gives_up_past_the_budget() {
+        let index = test_index();
+        let searcher = index.searcher();
+        let segment = &searcher.segment_readers()[0];
+        let field_bytes = uninvert(segment, Field(0), Type::Str, IndexRecordOption::Basic, usize::MAX).unwrap().num_bytes;
+        assert!(uninvert(segment, Field(0), Type::Str, IndexRecordOption::Basic, field_bytes / 2).is_none());
+
+        let cache = UninvertedCache::new(field_bytes / 2, EvictionPolicy::Lru);
+        assert!(cache.get_or_build(segment, Field(0), Type::Str, IndexRecordOption::Basic).is_none());
+        assert!(cache.get_or_build(segment, Field(0), Type::Str, IndexRecordOption::Basic).is_none());
+        let stats = cache.stats(&index);
+        assert_eq!((stats.misses, stats.bypassed, stats.oversize), (1, 2, 1));
+    }
+
+    #[test]
+    fn test_cached_reconstruction_matches_plain_reconstruction() {
+        let index = test_index();
+        let segment = index.searchable_segment_ids().unwrap()[0];
+        let mut docs = HashMap::new();
+        docs.insert(segment, vec![1, 4, 9]);
+        let cache = UninvertedCache::new(1024 * 1024, EvictionPolicy::Lru);
+        for _ in 0..2 {
+            assert_eq!(cache.reconstruct(&index, "b", &docs).unwrap(), reconstruct(&index, "b", &docs).unwrap());
+        }
+        assert_eq!(cache.stats(&index).hits, 1);
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::BTreeMap;
+use actix_web::HttpRequest;
+use actix_web::HttpResponse;
+use actix_web::Query;
+use failure::err_msg;
+use failure::Error;
+use tantivy::Index;
+use tantivy::schema::Type;
+
+use top_terms::for_each_merged_term;
+use output::print_json;
+use output::print_table;
+use output::OutputFormat;
+use State;
+
+const ZIPF_WIDTH: f64 = 600.0;
+const ZIPF_HEIGHT: f64 = 300.0;
+const ZIPF_MAX_POINTS: usize = 200;
+
+#[derive(Serialize)]
+pub struct SegmentTerms {
+    segment: String,
+    num_terms: usize,
+}
+
+#[derive(Serialize)]
+pub struct LengthBucket {
+    length: usize,
+    count: u64,
+    percent: String,
+}
+
+#[derive(Serialize)]
+pub struct VocabularyData {
+    field: String,
+    unique_terms: u64,
+    segments: Vec<SegmentTerms>,
+    term_lengths: Vec<LengthBucket>,
+    max_doc_freq: i64,
+    zipf_points: String,
+    zipf_width: f64,
+    zipf_height: f64,
+}
+
+/// Rank/frequency pairs on a log-log scale as an SVG polyline `points` attribute.
+///
+/// Ranks are sampled geometrically so the line stays small for large vocabularies.
+fn zipf_points(doc_freqs: &[i64]) -> String {
+    if doc_freqs.is_empty() {
+        return String::new();
+    }
+    let max_rank = (doc_freqs.len() as f64).log10().max(1.0);
+    let max_freq = (doc_freqs[0] as f64).log10().max(1.0);
+    let growth = (doc_freqs.len() as f64).powf(1.0 / ZIPF_MAX_POINTS as f64);
+
+    let mut points = Vec::new();
+    let mut rank = 1.0f64;
+    while (rank as usize) <= doc_freqs.len() {
+        let r = rank as usize;
+        let x = (r as f64).log10() / max_rank * ZIPF_WIDTH;
+        let y = ZIPF_HEIGHT - (doc_freqs[r - 1] as f64).log10() / max_freq * ZIPF_HEIGHT;
+        points.push(format!("{:.1},{:.1}", x, y));
+        rank = (rank * growth).max(r as f64 + 1.0);
+    }
+    points.join(" ")
+}
+
+/// Term dictionary statistics for a field: per-segment sizes, the merged vocabulary
+/// size, term lengths and the rank/frequency distribution.
+pub fn vocabulary(index: &Index, field_name: &str) -> Result<VocabularyData, Error> {
+    let schema = index.schema();
+    let field = schema.get_field(field_name).ok_or(err_msg("Sorry, that field does not exist!"))?;
+    let field_type = schema.get_field_entry(field).field_type();
+    if field_type.get_index_record_option().is_none() {
+        return Err(err_msg("Only indexed fields have a term dictionary"));
+    }
+    let is_text = match field_type.value_type() {
+        Type::Str => true,
+        _ => false,
+    };
+
+    let searcher = index.searcher();
+    let segments = searcher.segment_readers()
+        .iter()
+        .map(|segment| SegmentTerms {
+            segment: segment.segment_id().uuid_string(),
+            num_terms: segment.inverted_index(field).terms().num_terms(),
+        })
+        .collect();
+
+    let mut unique_terms = 0;
+    let mut lengths = BTreeMap::new();
+    let mut doc_freqs = Vec::new();
+    for_each_merged_term(index, field, |key, doc_freq| {
+        unique_terms += 1;
+        if is_text {
+            let length = String::from_utf8_lossy(key).chars().count();
+            *lengths.entry(length).or_insert(0u64) += 1;
+        }
+        doc_freqs.push(doc_freq);
+    });
+    doc_freqs.sort_unstable_by(|x, y| y.cmp(x));
+
+    let max_count = lengths.values().cloned().max().unwrap_or(0);
+    let term_lengths = lengths.into_iter()
+        .map(|(length, count)| LengthBucket {
+            length,
+            count,
+            percent: format!("{:.1}", count as f64 * 100.0 / max_count as f64),
+        })
+        .collect();
+
+    Ok(VocabularyData {
+        field: field_name.to_string(),
+        unique_terms,
+        segments,
+        term_lengths,
+        max_doc_freq: doc_freqs.first().cloned().unwrap_or(0),
+        zipf_points: zipf_points(&doc_freqs),
+        zipf_width: ZIPF_WIDTH,
+        zipf_height: ZIPF_HEIGHT,
+    })
+}
+
+#[derive(Deserialize)]
+pub struct VocabularyQuery {
+    field: String,
+}
+
+pub(crate) fn handle_vocabulary(req: (HttpRequest<State>, Query<VocabularyQuery>)) -> Result<HttpResponse, Error> {
+    let (req, params) = req;
+    let state = req.state();
+    let data = vocabulary(&state.index, &params.field)?;
+    Ok(state.render_template("vocabulary", &data)?)
+}
+
+pub fn run_vocabulary(index: &Index, field_name: &str, format: OutputFormat) -> Result<(), Error> {
+    let data = vocabulary(index, field_name)?;
+    match format {
+        OutputFormat::Json => print_json(&data)?,
+        OutputFormat::Table => {
+            println!("Unique terms: {}", data.unique_terms);
+            println!("Highest doc frequency: {}", data.max_doc_freq);
+            println!();
+            let rows = data.segments.iter()
+                .map(|x| vec![x.segment.clone(), x.num_terms.to_string()])
+                .collect::<Vec<_>>();
+            print_table(&["segment", "terms"], &rows);
+            println!();
+            let rows = data.term_lengths.iter()
+                .map(|x| vec![x.length.to_string(), x.count.to_string(), x.percent.clone()])
+                .collect::<Vec<_>>();
+            print_table(&["term length", "count", "percent"], &rows);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tantivy::Document;
+    use tantivy::merge_policy::NoMergePolicy;
+    use tantivy::schema::SchemaBuilder;
+    use tantivy::schema::TEXT;
+
+    #[test]
+    fn test_zipf_points() {
+        assert_eq!(zipf_points(&[]), "");
+        assert_eq!(zipf_points(&[100, 10, 1]), "0.0,0.0 180.6,150.0 286.3,300.0");
+    }
+
+    #[test]
+    fn test_vocabulary_merges_segments() {
+        let mut schema_builder = SchemaBuilder::default();
+        let body = schema_builder.add_text_field("body", TEXT);
+        let index = Index::create_in_ram(schema_builder.build());
+        let mut writer = index.writer_with_num_threads(1, 40_000_000).unwrap();
+        writer.set_merge_policy(Box::new(NoMergePolicy));
+        for segment in vec![vec!["apple banana", "apple"], vec!["apple cherry"]] {
+            for text in segment {
+                let mut document = Document::default();
+                document.add_text(body, text);
+                writer.add_document(document);
+            }
+            writer.commit().unwrap();
+        }
+        index.load_searchers().unwrap();
+
+        let data = vocabulary(&index, "body").unwrap();
+        assert_eq!(data.unique_terms, 3);
+        assert_eq!(data.max_doc_freq, 3);
+        let mut num_terms = data.segments.iter().map(|x| x.num_terms).collect::<Vec<_>>();
+        num_terms.sort();
+        assert_eq!(num_terms, vec![2, 2]);
+        let lengths = data.term_lengths.iter().map(|x| (x.length, x.count)).collect::<Vec<_>>();
+        assert_eq!(lengths, vec![(5, 1), (6, 2)]);
+    }
+}